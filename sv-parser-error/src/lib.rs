@@ -1,3 +1,4 @@
+use std::fmt;
 use std::path::PathBuf;
 use thiserror::Error;
 
@@ -13,23 +14,199 @@ pub enum Error {
         source: std::io::Error,
         path: PathBuf,
     },
-    #[error("Include error")]
+    #[error("Include error{}", at(.location))]
     Include {
-        #[from]
+        #[source]
         source: Box<Error>,
+        location: Option<Box<SourceLocation>>,
+    },
+    #[error("Parse error{}{}", at(.location), expected_message(.expected, .rule))]
+    Parse {
+        location: Option<Box<SourceLocation>>,
+        expected: Vec<String>,
+        rule: Option<String>,
     },
-    #[error("Parse error: {0:?}")]
-    Parse(Option<(PathBuf, usize)>),
-    #[error("Preprocess error")]
-    Preprocess,
-    #[error("Define argument not found: {0}")]
-    DefineArgNotFound(String),
-    #[error("Define not found: {0}")]
-    DefineNotFound(String),
-    #[error("Define must have argument")]
-    DefineNoArgs,
-    #[error("Exceed recursive limit")]
-    ExceedRecursiveLimit,
-    #[error("Include line can't have other items")]
-    IncludeLine,
+    #[error("Preprocess error{}", at(.0))]
+    Preprocess(Option<Box<SourceLocation>>),
+    #[error("Define argument not found: {0}{}", at(.1))]
+    DefineArgNotFound(String, Option<Box<SourceLocation>>),
+    #[error("Define not found: {0}{}", at(.1))]
+    DefineNotFound(String, Option<Box<SourceLocation>>),
+    #[error("Define must have argument{}", at(.0))]
+    DefineNoArgs(Option<Box<SourceLocation>>),
+    #[error("Exceed recursive limit{}", at(.0))]
+    ExceedRecursiveLimit(Option<Box<SourceLocation>>),
+    #[error("Include line can't have other items{}", at(.0))]
+    IncludeLine(Option<Box<SourceLocation>>),
+    #[error("Filelist error: {0}{}", at(.1))]
+    Filelist(String, Option<Box<SourceLocation>>),
+    #[error("Library map error: {0}{}", at(.1))]
+    LibraryMap(String, Option<Box<SourceLocation>>),
+    #[error("Invalid define: {0:?}")]
+    DefineArg(String),
+}
+
+impl Error {
+    /// Get the source location of the error
+    pub fn location(&self) -> Option<&SourceLocation> {
+        match self {
+            Error::Io(_) | Error::File { .. } | Error::DefineArg(_) => None,
+            Error::Include { location, .. } => location.as_deref(),
+            Error::Parse { location, .. } => location.as_deref(),
+            Error::Preprocess(x) => x.as_deref(),
+            Error::DefineArgNotFound(_, x) => x.as_deref(),
+            Error::DefineNotFound(_, x) => x.as_deref(),
+            Error::DefineNoArgs(x) => x.as_deref(),
+            Error::ExceedRecursiveLimit(x) => x.as_deref(),
+            Error::IncludeLine(x) => x.as_deref(),
+            Error::Filelist(_, x) => x.as_deref(),
+            Error::LibraryMap(_, x) => x.as_deref(),
+        }
+    }
+
+    /// Get the source location of the error, or the location of the innermost included error
+    pub fn innermost_location(&self) -> Option<&SourceLocation> {
+        match self {
+            Error::Include { source, location } => {
+                source.innermost_location().or(location.as_deref())
+            }
+            x => x.location(),
+        }
+    }
+
+    fn location_mut(&mut self) -> Option<&mut Option<Box<SourceLocation>>> {
+        match self {
+            Error::Io(_) | Error::File { .. } | Error::DefineArg(_) => None,
+            Error::Include { location, .. } => Some(location),
+            Error::Parse { location, .. } => Some(location),
+            Error::Preprocess(x) => Some(x),
+            Error::DefineArgNotFound(_, x) => Some(x),
+            Error::DefineNotFound(_, x) => Some(x),
+            Error::DefineNoArgs(x) => Some(x),
+            Error::ExceedRecursiveLimit(x) => Some(x),
+            Error::IncludeLine(x) => Some(x),
+            Error::Filelist(_, x) => Some(x),
+            Error::LibraryMap(_, x) => Some(x),
+        }
+    }
+
+    /// Record that the error occurred through the expansion
+    pub fn expanded(mut self, expansion: Expansion) -> Self {
+        self.push_expansion(&expansion);
+        self
+    }
+
+    fn push_expansion(&mut self, expansion: &Expansion) {
+        if let Error::Include { source, .. } = self {
+            source.push_expansion(expansion);
+        }
+        if let Some(Some(x)) = self.location_mut() {
+            x.expansions.push(expansion.clone());
+        }
+    }
+}
+
+// `Error::Include` had `#[from]` before it got `location`
+impl From<Box<Error>> for Error {
+    fn from(source: Box<Error>) -> Self {
+        Error::Include {
+            source,
+            location: None,
+        }
+    }
+}
+
+/// Position in a source file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub path: PathBuf,
+    /// Byte range in the file before `` `line `` directives are applied
+    pub begin: usize,
+    pub end: usize,
+    /// 1-origin line number of `path` after `` `line `` directives are applied
+    pub line: usize,
+    /// 1-origin byte column
+    pub column: usize,
+}
+
+/// `include directive or text macro usage which produced the text
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expansion {
+    Include(Location),
+    Macro(String),
+}
+
+/// Location of an error with the expansions which led there, innermost first
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub location: Location,
+    pub expansions: Vec<Expansion>,
+}
+
+impl SourceLocation {
+    pub fn new(location: Location) -> Self {
+        SourceLocation {
+            location,
+            expansions: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.path.display(), self.line, self.column)
+    }
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.location)?;
+        for x in &self.expansions {
+            match x {
+                Expansion::Include(x) => write!(f, ", included from {}", x)?,
+                Expansion::Macro(x) => write!(f, ", in expansion of macro `{}`", x)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn at(location: &Option<Box<SourceLocation>>) -> String {
+    if let Some(x) = location {
+        format!(" at {}", x)
+    } else {
+        String::new()
+    }
+}
+
+fn expected_message(expected: &[String], rule: &Option<String>) -> String {
+    let mut ret = String::new();
+    if !expected.is_empty() {
+        ret.push_str(&format!(", {}", expected_list(expected)));
+    }
+    if let Some(rule) = rule {
+        ret.push_str(&format!(" in {}", rule));
+    }
+    ret
+}
+
+/// Format expected tokens like "expected `;`, `,` or identifier"
+pub fn expected_list(expected: &[String]) -> String {
+    let quote = |x: &String| {
+        if x == "identifier" {
+            x.clone()
+        } else {
+            format!("`{}`", x)
+        }
+    };
+
+    let mut ret = String::from("expected ");
+    if let Some((last, rest)) = expected.split_last() {
+        if !rest.is_empty() {
+            let rest: Vec<_> = rest.iter().map(quote).collect();
+            ret.push_str(&format!("{} or ", rest.join(", ")));
+        }
+        ret.push_str(&quote(last));
+    }
+    ret
 }
@@ -177,6 +177,7 @@ fn impl_ref_node(ast: &DeriveInput) -> TokenStream {
 
     let mut next_items = quote! {};
     let mut into_iter_items = quote! {};
+    let mut as_ptr_items = quote! {};
     for v in &data.variants {
         let ident = &v.ident;
         next_items = quote! {
@@ -187,6 +188,10 @@ fn impl_ref_node(ast: &DeriveInput) -> TokenStream {
             #into_iter_items
             RefNode::#ident(x) => x.into_iter(),
         };
+        as_ptr_items = quote! {
+            #as_ptr_items
+            RefNode::#ident(x) => *x as *const _ as *const (),
+        };
     }
 
     let name = &ast.ident;
@@ -197,6 +202,15 @@ fn impl_ref_node(ast: &DeriveInput) -> TokenStream {
                     #next_items
                 }
             }
+
+            /// Address of the referenced node.
+            /// A node in a tree is identified by this and the variant,
+            /// because a node and its first member can have the same address.
+            pub fn as_ptr(&self) -> *const () {
+                match self {
+                    #as_ptr_items
+                }
+            }
         }
 
         impl<'a> IntoIterator for #name<'a> {
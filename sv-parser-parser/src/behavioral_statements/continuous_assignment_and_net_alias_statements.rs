@@ -5,7 +5,10 @@ use crate::*;
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn continuous_assign(s: Span) -> IResult<Span, ContinuousAssign> {
-    alt((continuous_assign_net, continuous_assign_variable))(s)
+    rule(
+        "continuous_assign",
+        alt((continuous_assign_net, continuous_assign_variable)),
+    )(s)
 }
 
 #[tracable_parser]
@@ -37,62 +37,65 @@ pub(crate) fn statement(s: Span) -> IResult<Span, Statement> {
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn statement_item(s: Span) -> IResult<Span, StatementItem> {
-    alt((
-        map(pair(blocking_assignment, symbol(";")), |x| {
-            StatementItem::BlockingAssignment(Box::new(x))
-        }),
-        map(pair(nonblocking_assignment, symbol(";")), |x| {
-            StatementItem::NonblockingAssignment(Box::new(x))
-        }),
-        map(pair(procedural_continuous_assignment, symbol(";")), |x| {
-            StatementItem::ProceduralContinuousAssignment(Box::new(x))
-        }),
-        map(case_statement, |x| {
-            StatementItem::CaseStatement(Box::new(x))
-        }),
-        map(conditional_statement, |x| {
-            StatementItem::ConditionalStatement(Box::new(x))
-        }),
-        map(pair(inc_or_dec_expression, symbol(";")), |x| {
-            StatementItem::IncOrDecExpression(Box::new(x))
-        }),
-        map(subroutine_call_statement, |x| {
-            StatementItem::SubroutineCallStatement(Box::new(x))
-        }),
-        map(disable_statement, |x| {
-            StatementItem::DisableStatement(Box::new(x))
-        }),
-        map(event_trigger, |x| StatementItem::EventTrigger(Box::new(x))),
-        map(loop_statement, |x| {
-            StatementItem::LoopStatement(Box::new(x))
-        }),
-        map(jump_statement, |x| {
-            StatementItem::JumpStatement(Box::new(x))
-        }),
-        map(par_block, |x| StatementItem::ParBlock(Box::new(x))),
-        map(procedural_timing_control_statement, |x| {
-            StatementItem::ProceduralTimingControlStatement(Box::new(x))
-        }),
-        map(seq_block, |x| StatementItem::SeqBlock(Box::new(x))),
-        map(wait_statement, |x| {
-            StatementItem::WaitStatement(Box::new(x))
-        }),
-        map(procedural_assertion_statement, |x| {
-            StatementItem::ProceduralAssertionStatement(Box::new(x))
-        }),
-        map(pair(clocking_drive, symbol(";")), |x| {
-            StatementItem::ClockingDrive(Box::new(x))
-        }),
-        map(randsequence_statement, |x| {
-            StatementItem::RandsequenceStatement(Box::new(x))
-        }),
-        map(randcase_statement, |x| {
-            StatementItem::RandcaseStatement(Box::new(x))
-        }),
-        map(expect_property_statement, |x| {
-            StatementItem::ExpectPropertyStatement(Box::new(x))
-        }),
-    ))(s)
+    rule(
+        "statement_item",
+        alt((
+            map(pair(blocking_assignment, symbol(";")), |x| {
+                StatementItem::BlockingAssignment(Box::new(x))
+            }),
+            map(pair(nonblocking_assignment, symbol(";")), |x| {
+                StatementItem::NonblockingAssignment(Box::new(x))
+            }),
+            map(pair(procedural_continuous_assignment, symbol(";")), |x| {
+                StatementItem::ProceduralContinuousAssignment(Box::new(x))
+            }),
+            map(case_statement, |x| {
+                StatementItem::CaseStatement(Box::new(x))
+            }),
+            map(conditional_statement, |x| {
+                StatementItem::ConditionalStatement(Box::new(x))
+            }),
+            map(pair(inc_or_dec_expression, symbol(";")), |x| {
+                StatementItem::IncOrDecExpression(Box::new(x))
+            }),
+            map(subroutine_call_statement, |x| {
+                StatementItem::SubroutineCallStatement(Box::new(x))
+            }),
+            map(disable_statement, |x| {
+                StatementItem::DisableStatement(Box::new(x))
+            }),
+            map(event_trigger, |x| StatementItem::EventTrigger(Box::new(x))),
+            map(loop_statement, |x| {
+                StatementItem::LoopStatement(Box::new(x))
+            }),
+            map(jump_statement, |x| {
+                StatementItem::JumpStatement(Box::new(x))
+            }),
+            map(par_block, |x| StatementItem::ParBlock(Box::new(x))),
+            map(procedural_timing_control_statement, |x| {
+                StatementItem::ProceduralTimingControlStatement(Box::new(x))
+            }),
+            map(seq_block, |x| StatementItem::SeqBlock(Box::new(x))),
+            map(wait_statement, |x| {
+                StatementItem::WaitStatement(Box::new(x))
+            }),
+            map(procedural_assertion_statement, |x| {
+                StatementItem::ProceduralAssertionStatement(Box::new(x))
+            }),
+            map(pair(clocking_drive, symbol(";")), |x| {
+                StatementItem::ClockingDrive(Box::new(x))
+            }),
+            map(randsequence_statement, |x| {
+                StatementItem::RandsequenceStatement(Box::new(x))
+            }),
+            map(randcase_statement, |x| {
+                StatementItem::RandcaseStatement(Box::new(x))
+            }),
+            map(expect_property_statement, |x| {
+                StatementItem::ExpectPropertyStatement(Box::new(x))
+            }),
+        )),
+    )(s)
 }
 
 #[tracable_parser]
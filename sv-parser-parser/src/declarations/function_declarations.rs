@@ -41,10 +41,13 @@ pub(crate) fn function_declaration(s: Span) -> IResult<Span, FunctionDeclaration
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn function_body_declaration(s: Span) -> IResult<Span, FunctionBodyDeclaration> {
-    alt((
-        function_body_declaration_without_port,
-        function_body_declaration_with_port,
-    ))(s)
+    rule(
+        "function_body_declaration",
+        alt((
+            function_body_declaration_without_port,
+            function_body_declaration_with_port,
+        )),
+    )(s)
 }
 
 #[tracable_parser]
@@ -5,10 +5,13 @@ use crate::*;
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn local_parameter_declaration(s: Span) -> IResult<Span, LocalParameterDeclaration> {
-    alt((
-        local_parameter_declaration_param,
-        local_parameter_declaration_type,
-    ))(s)
+    rule(
+        "local_parameter_declaration",
+        alt((
+            local_parameter_declaration_param,
+            local_parameter_declaration_type,
+        )),
+    )(s)
 }
 
 #[tracable_parser]
@@ -62,7 +65,10 @@ pub(crate) fn local_parameter_declaration_type(
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn parameter_declaration(s: Span) -> IResult<Span, ParameterDeclaration> {
-    alt((parameter_declaration_param, parameter_declaration_type))(s)
+    rule(
+        "parameter_declaration",
+        alt((parameter_declaration_param, parameter_declaration_type)),
+    )(s)
 }
 
 #[tracable_parser]
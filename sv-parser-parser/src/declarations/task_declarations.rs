@@ -14,10 +14,13 @@ pub(crate) fn task_declaration(s: Span) -> IResult<Span, TaskDeclaration> {
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn task_body_declaration(s: Span) -> IResult<Span, TaskBodyDeclaration> {
-    alt((
-        task_body_declaration_without_port,
-        task_body_declaration_with_port,
-    ))(s)
+    rule(
+        "task_body_declaration",
+        alt((
+            task_body_declaration_without_port,
+            task_body_declaration_with_port,
+        )),
+    )(s)
 }
 
 #[tracable_parser]
@@ -5,18 +5,21 @@ use crate::*;
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn data_declaration(s: Span) -> IResult<Span, DataDeclaration> {
-    alt((
-        data_declaration_variable,
-        map(type_declaration, |x| {
-            DataDeclaration::TypeDeclaration(Box::new(x))
-        }),
-        map(package_import_declaration, |x| {
-            DataDeclaration::PackageImportDeclaration(Box::new(x))
-        }),
-        map(net_type_declaration, |x| {
-            DataDeclaration::NetTypeDeclaration(Box::new(x))
-        }),
-    ))(s)
+    rule(
+        "data_declaration",
+        alt((
+            data_declaration_variable,
+            map(type_declaration, |x| {
+                DataDeclaration::TypeDeclaration(Box::new(x))
+            }),
+            map(package_import_declaration, |x| {
+                DataDeclaration::PackageImportDeclaration(Box::new(x))
+            }),
+            map(net_type_declaration, |x| {
+                DataDeclaration::NetTypeDeclaration(Box::new(x))
+            }),
+        )),
+    )(s)
 }
 
 #[tracable_parser]
@@ -147,11 +150,14 @@ pub(crate) fn genvar_declaration(s: Span) -> IResult<Span, GenvarDeclaration> {
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn net_declaration(s: Span) -> IResult<Span, NetDeclaration> {
-    alt((
-        net_declaration_interconnect,
-        net_declaration_net_type,
-        net_declaration_net_type_identifier,
-    ))(s)
+    rule(
+        "net_declaration",
+        alt((
+            net_declaration_interconnect,
+            net_declaration_net_type,
+            net_declaration_net_type_identifier,
+        )),
+    )(s)
 }
 
 #[tracable_parser]
@@ -460,12 +460,12 @@ pub(crate) fn conditional_compiler_directive(
 pub(crate) fn ifdef_directive(s: Span) -> IResult<Span, IfdefDirective> {
     let (s, a) = symbol("`")(s)?;
     let (s, b) = keyword("ifdef")(s)?;
-    let (s, c) = text_macro_identifier(s)?;
+    let (s, c) = ifdef_condition(s)?;
     let (s, d) = ifdef_group_of_lines(s)?;
     let (s, e) = many0(tuple((
         symbol("`"),
         keyword("elsif"),
-        text_macro_identifier,
+        ifdef_condition,
         elsif_group_of_lines,
     )))(s)?;
     let (s, f) = opt(tuple((symbol("`"), keyword("else"), else_group_of_lines)))(s)?;
@@ -484,12 +484,12 @@ pub(crate) fn ifdef_directive(s: Span) -> IResult<Span, IfdefDirective> {
 pub(crate) fn ifndef_directive(s: Span) -> IResult<Span, IfndefDirective> {
     let (s, a) = symbol("`")(s)?;
     let (s, b) = keyword("ifndef")(s)?;
-    let (s, c) = text_macro_identifier(s)?;
+    let (s, c) = ifdef_condition(s)?;
     let (s, d) = ifndef_group_of_lines(s)?;
     let (s, e) = many0(tuple((
         symbol("`"),
         keyword("elsif"),
-        text_macro_identifier,
+        ifdef_condition,
         elsif_group_of_lines,
     )))(s)?;
     let (s, f) = opt(tuple((symbol("`"), keyword("else"), else_group_of_lines)))(s)?;
@@ -503,6 +503,76 @@ pub(crate) fn ifndef_directive(s: Span) -> IResult<Span, IfndefDirective> {
     ))
 }
 
+// An expression other than a single identifier must be enclosed in parentheses
+// so that the group of lines following the condition is never taken as an operand.
+#[tracable_parser]
+#[packrat_parser]
+pub(crate) fn ifdef_condition(s: Span) -> IResult<Span, IfdefCondition> {
+    alt((
+        map(ifdef_macro_expression_paren, |x| {
+            IfdefCondition::IfdefMacroExpression(Box::new(x))
+        }),
+        map(text_macro_identifier, |x| {
+            IfdefCondition::TextMacroIdentifier(Box::new(x))
+        }),
+    ))(s)
+}
+
+#[tracable_parser]
+#[packrat_parser]
+pub(crate) fn ifdef_macro_expression(s: Span) -> IResult<Span, IfdefMacroExpression> {
+    alt((
+        ifdef_macro_expression_binary,
+        ifdef_macro_expression_primary,
+    ))(s)
+}
+
+#[tracable_parser]
+#[packrat_parser]
+pub(crate) fn ifdef_macro_expression_primary(s: Span) -> IResult<Span, IfdefMacroExpression> {
+    alt((
+        map(text_macro_identifier, |x| {
+            IfdefMacroExpression::TextMacroIdentifier(Box::new(x))
+        }),
+        ifdef_macro_expression_paren,
+        ifdef_macro_expression_not,
+    ))(s)
+}
+
+#[tracable_parser]
+#[packrat_parser]
+pub(crate) fn ifdef_macro_expression_paren(s: Span) -> IResult<Span, IfdefMacroExpression> {
+    let (s, a) = paren(ifdef_macro_expression)(s)?;
+    Ok((
+        s,
+        IfdefMacroExpression::Paren(Box::new(IfdefMacroExpressionParen { nodes: (a,) })),
+    ))
+}
+
+#[tracable_parser]
+#[packrat_parser]
+pub(crate) fn ifdef_macro_expression_not(s: Span) -> IResult<Span, IfdefMacroExpression> {
+    let (s, a) = symbol("!")(s)?;
+    let (s, b) = ifdef_macro_expression_primary(s)?;
+    Ok((
+        s,
+        IfdefMacroExpression::Not(Box::new(IfdefMacroExpressionNot { nodes: (a, b) })),
+    ))
+}
+
+#[recursive_parser]
+#[tracable_parser]
+#[packrat_parser]
+pub(crate) fn ifdef_macro_expression_binary(s: Span) -> IResult<Span, IfdefMacroExpression> {
+    let (s, a) = ifdef_macro_expression(s)?;
+    let (s, b) = alt((symbol("&&"), symbol("||"), symbol("->"), symbol("<->")))(s)?;
+    let (s, c) = ifdef_macro_expression(s)?;
+    Ok((
+        s,
+        IfdefMacroExpression::Binary(Box::new(IfdefMacroExpressionBinary { nodes: (a, b, c) })),
+    ))
+}
+
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn ifdef_group_of_lines(s: Span) -> IResult<Span, IfdefGroupOfLines> {
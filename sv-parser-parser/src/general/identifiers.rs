@@ -669,19 +669,20 @@ pub(crate) fn signal_identifier(s: Span) -> IResult<Span, SignalIdentifier> {
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn simple_identifier(s: Span) -> IResult<Span, SimpleIdentifier> {
-    let (s, a) = ws(simple_identifier_impl)(s)?;
+    let (s, a) = expected("identifier", ws(simple_identifier_impl))(s)?;
     Ok((s, SimpleIdentifier { nodes: a }))
 }
 
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn simple_identifier_exact(s: Span) -> IResult<Span, SimpleIdentifier> {
-    let (s, a) = no_ws(simple_identifier_impl)(s)?;
+    let (s, a) = expected("identifier", no_ws(simple_identifier_impl))(s)?;
     Ok((s, SimpleIdentifier { nodes: a }))
 }
 
 #[tracable_parser]
 pub(crate) fn simple_identifier_impl(s: Span) -> IResult<Span, Locate> {
+    let input = s;
     let (s, a) = is_a(AZ_)(s)?;
     let (s, b) = opt(is_a(AZ09_DOLLAR))(s)?;
     let a = if let Some(b) = b {
@@ -690,7 +691,7 @@ pub(crate) fn simple_identifier_impl(s: Span) -> IResult<Span, Locate> {
         a
     };
     if is_keyword(&a) {
-        Err(Err::Error(make_error(s, ErrorKind::Fix)))
+        Err(Err::Error(make_error(input, ErrorKind::Fix)))
     } else {
         Ok((s, into_locate(a)))
     }
@@ -5,6 +5,7 @@ pub mod keywords;
 pub mod utils;
 pub(crate) use keywords::*;
 pub(crate) use utils::*;
+pub use utils::{expected_tokens, Expected, KeywordVersions};
 
 mod tests;
 
@@ -100,6 +101,41 @@ pub fn sv_parser_incomplete(s: Span) -> IResult<Span, SourceText> {
     source_text_incomplete(s)
 }
 
+/// Parse the white spaces and timeunits at the beginning of `source_text`.
+/// `error_texts` are the ranges parsed as `WhiteSpace::ErrorText`.
+/// The keyword versions after the header are returned to parse the next description.
+pub fn sv_parser_recovery_header<'a>(
+    s: Span<'a>,
+    error_texts: &[(usize, usize)],
+) -> IResult<
+    Span<'a>,
+    (
+        Vec<WhiteSpace>,
+        Option<TimeunitsDeclaration>,
+        KeywordVersions,
+    ),
+> {
+    init_recovery(error_texts, &KeywordVersions::default());
+    let (s, (a, b)) = pair(many0(white_space), opt(timeunits_declaration))(s)?;
+    Ok((s, (a, b, save_version())))
+}
+
+/// Parse a `description` of `source_text`, or `None` at the end of input.
+/// Each description can be parsed again after `error_texts` is changed.
+/// `versions` are the keyword versions after the previous description,
+/// and the versions after this description are returned.
+pub fn sv_parser_recovery_description<'a>(
+    s: Span<'a>,
+    error_texts: &[(usize, usize)],
+    versions: &KeywordVersions,
+) -> IResult<Span<'a>, Option<(Description, KeywordVersions)>> {
+    init_recovery(error_texts, versions);
+    alt((
+        map(eof, |_| None),
+        map(description, |x| Some((x, save_version()))),
+    ))(s)
+}
+
 pub fn lib_parser(s: Span) -> IResult<Span, LibraryText> {
     init();
     library_text(s)
@@ -119,4 +155,13 @@ fn init() {
     nom_packrat::init!();
     clear_directive();
     clear_version();
+    clear_error_texts();
+    clear_expected();
+}
+
+// The parsed results are cleared for each part of recovery, but the keyword versions are kept
+fn init_recovery(error_texts: &[(usize, usize)], versions: &KeywordVersions) {
+    init();
+    restore_version(versions);
+    set_error_texts(error_texts);
 }
@@ -5,20 +5,23 @@ use crate::*;
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn class_item(s: Span) -> IResult<Span, ClassItem> {
-    alt((
-        class_item_property,
-        class_item_method,
-        class_item_constraint,
-        class_item_declaration,
-        class_item_covergroup,
-        map(pair(local_parameter_declaration, symbol(";")), |x| {
-            ClassItem::LocalParameterDeclaration(Box::new(x))
-        }),
-        map(pair(parameter_declaration, symbol(";")), |x| {
-            ClassItem::ParameterDeclaration(Box::new(x))
-        }),
-        map(symbol(";"), |x| ClassItem::Empty(Box::new(x))),
-    ))(s)
+    rule(
+        "class_item",
+        alt((
+            class_item_property,
+            class_item_method,
+            class_item_constraint,
+            class_item_declaration,
+            class_item_covergroup,
+            map(pair(local_parameter_declaration, symbol(";")), |x| {
+                ClassItem::LocalParameterDeclaration(Box::new(x))
+            }),
+            map(pair(parameter_declaration, symbol(";")), |x| {
+                ClassItem::ParameterDeclaration(Box::new(x))
+            }),
+            map(symbol(";"), |x| ClassItem::Empty(Box::new(x))),
+        )),
+    )(s)
 }
 
 #[tracable_parser]
@@ -146,7 +146,7 @@ pub(crate) fn liblist_clause(s: Span) -> IResult<Span, LiblistClause> {
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn use_clause(s: Span) -> IResult<Span, UseClause> {
-    alt((use_clause_cell, use_clause_named, use_clause_cell_named))(s)
+    alt((use_clause_cell_named, use_clause_named, use_clause_cell))(s)
 }
 
 #[tracable_parser]
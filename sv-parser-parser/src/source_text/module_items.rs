@@ -123,14 +123,17 @@ pub(crate) fn module_common_item(s: Span) -> IResult<Span, ModuleCommonItem> {
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn module_item(s: Span) -> IResult<Span, ModuleItem> {
-    alt((
-        map(pair(port_declaration, symbol(";")), |x| {
-            ModuleItem::PortDeclaration(Box::new(x))
-        }),
-        map(non_port_module_item, |x| {
-            ModuleItem::NonPortModuleItem(Box::new(x))
-        }),
-    ))(s)
+    rule(
+        "module_item",
+        alt((
+            map(pair(port_declaration, symbol(";")), |x| {
+                ModuleItem::PortDeclaration(Box::new(x))
+            }),
+            map(non_port_module_item, |x| {
+                ModuleItem::NonPortModuleItem(Box::new(x))
+            }),
+        )),
+    )(s)
 }
 
 #[tracable_parser]
@@ -265,30 +268,33 @@ pub(crate) fn module_or_generate_item_declaration_disable(
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn non_port_module_item(s: Span) -> IResult<Span, NonPortModuleItem> {
-    alt((
-        map(generate_region, |x| {
-            NonPortModuleItem::GenerateRegion(Box::new(x))
-        }),
-        map(module_or_generate_item, |x| {
-            NonPortModuleItem::ModuleOrGenerateItem(Box::new(x))
-        }),
-        map(specify_block, |x| {
-            NonPortModuleItem::SpecifyBlock(Box::new(x))
-        }),
-        non_port_module_item_specparam,
-        map(program_declaration, |x| {
-            NonPortModuleItem::ProgramDeclaration(Box::new(x))
-        }),
-        map(module_declaration, |x| {
-            NonPortModuleItem::ModuleDeclaration(Box::new(x))
-        }),
-        map(interface_declaration, |x| {
-            NonPortModuleItem::InterfaceDeclaration(Box::new(x))
-        }),
-        map(timeunits_declaration, |x| {
-            NonPortModuleItem::TimeunitsDeclaration(Box::new(x))
-        }),
-    ))(s)
+    rule(
+        "non_port_module_item",
+        alt((
+            map(generate_region, |x| {
+                NonPortModuleItem::GenerateRegion(Box::new(x))
+            }),
+            map(module_or_generate_item, |x| {
+                NonPortModuleItem::ModuleOrGenerateItem(Box::new(x))
+            }),
+            map(specify_block, |x| {
+                NonPortModuleItem::SpecifyBlock(Box::new(x))
+            }),
+            non_port_module_item_specparam,
+            map(program_declaration, |x| {
+                NonPortModuleItem::ProgramDeclaration(Box::new(x))
+            }),
+            map(module_declaration, |x| {
+                NonPortModuleItem::ModuleDeclaration(Box::new(x))
+            }),
+            map(interface_declaration, |x| {
+                NonPortModuleItem::InterfaceDeclaration(Box::new(x))
+            }),
+            map(timeunits_declaration, |x| {
+                NonPortModuleItem::TimeunitsDeclaration(Box::new(x))
+            }),
+        )),
+    )(s)
 }
 
 #[tracable_parser]
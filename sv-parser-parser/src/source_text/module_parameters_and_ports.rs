@@ -5,11 +5,14 @@ use crate::*;
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn parameter_port_list(s: Span) -> IResult<Span, ParameterPortList> {
-    alt((
-        parameter_port_list_assignment,
-        parameter_port_list_declaration,
-        parameter_port_list_empty,
-    ))(s)
+    rule(
+        "parameter_port_list",
+        alt((
+            parameter_port_list_assignment,
+            parameter_port_list_declaration,
+            parameter_port_list_empty,
+        )),
+    )(s)
 }
 
 #[tracable_parser]
@@ -94,30 +97,36 @@ pub(crate) fn parameter_port_declaration_type_list(
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn list_of_ports(s: Span) -> IResult<Span, ListOfPorts> {
-    let (s, a) = paren(list(symbol(","), port))(s)?;
+    let (s, a) = rule("list_of_ports", paren(list(symbol(","), port)))(s)?;
     Ok((s, ListOfPorts { nodes: (a,) }))
 }
 
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn list_of_port_declarations(s: Span) -> IResult<Span, ListOfPortDeclarations> {
-    let (s, a) = paren(opt(list(
-        symbol(","),
-        pair(many0(attribute_instance), ansi_port_declaration),
-    )))(s)?;
+    let (s, a) = rule(
+        "list_of_port_declarations",
+        paren(opt(list(
+            symbol(","),
+            pair(many0(attribute_instance), ansi_port_declaration),
+        ))),
+    )(s)?;
     Ok((s, ListOfPortDeclarations { nodes: (a,) }))
 }
 
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn port_declaration(s: Span) -> IResult<Span, PortDeclaration> {
-    alt((
-        port_declaration_inout,
-        port_declaration_input,
-        port_declaration_output,
-        port_declaration_ref,
-        port_declaration_interface,
-    ))(s)
+    rule(
+        "port_declaration",
+        alt((
+            port_declaration_inout,
+            port_declaration_input,
+            port_declaration_output,
+            port_declaration_ref,
+            port_declaration_interface,
+        )),
+    )(s)
 }
 
 #[tracable_parser]
@@ -5,20 +5,23 @@ use crate::*;
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn package_item(s: Span) -> IResult<Span, PackageItem> {
-    alt((
-        map(package_or_generate_item_declaration, |x| {
-            PackageItem::PackageOrGenerateItemDeclaration(Box::new(x))
-        }),
-        map(anonymous_program, |x| {
-            PackageItem::AnonymousProgram(Box::new(x))
-        }),
-        map(package_export_declaration, |x| {
-            PackageItem::PackageExportDeclaration(Box::new(x))
-        }),
-        map(timeunits_declaration, |x| {
-            PackageItem::TimeunitsDeclaration(Box::new(x))
-        }),
-    ))(s)
+    rule(
+        "package_item",
+        alt((
+            map(package_or_generate_item_declaration, |x| {
+                PackageItem::PackageOrGenerateItemDeclaration(Box::new(x))
+            }),
+            map(anonymous_program, |x| {
+                PackageItem::AnonymousProgram(Box::new(x))
+            }),
+            map(package_export_declaration, |x| {
+                PackageItem::PackageExportDeclaration(Box::new(x))
+            }),
+            map(timeunits_declaration, |x| {
+                PackageItem::TimeunitsDeclaration(Box::new(x))
+            }),
+        )),
+    )(s)
 }
 
 #[tracable_parser]
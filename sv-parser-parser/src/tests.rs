@@ -12013,6 +12013,35 @@ mod spec {
                 endmodule"##,
             Ok((_, _))
         );
+        test!(
+            source_text,
+            r##"module test;
+                  `ifdef ((first_block && !second_nest) || third)
+                    initial $display("first_block is defined");
+                  `elsif (second_block -> (first_block <-> third))
+                    initial $display("second_block defined, first_block is not");
+                  `endif
+                  `ifndef (last_result)
+                    initial $display("last_result not defined");
+                  `endif
+                endmodule"##,
+            Ok((_, _))
+        );
+        test!(
+            source_text,
+            r##"module test;
+                  initial begin
+                  `ifdef first_block
+                    -> ev;
+                  `endif
+                  end
+                endmodule"##,
+            Ok((_, _))
+        );
+        test!(ifdef_condition, r##"(A)"##, Ok((_, _)));
+        test!(ifdef_condition, r##"!A"##, Err(_));
+        test!(ifdef_condition, r##"(A) || B"##, Err(_));
+        test!(ifdef_condition, "(A)\n-> ev", Err(_));
         test!(source_text, r##"`timescale 1 ns / 1 ps"##, Ok((_, _)));
         test!(source_text, r##"`timescale 10 us / 100 ns"##, Ok((_, _)));
         test!(
@@ -15779,6 +15808,14 @@ mod spec {
                 endconfig"##,
             Ok((_, _))
         );
+        test!(
+            source_text,
+            r##"config cfg7;
+                  design rtlLib.top;
+                  cell foo use gateLib.bar .W(8), .D(4) ;
+                endconfig"##,
+            Ok((_, _))
+        );
         test!(
             library_text,
             r##"library lib1 "/proj/lib/foo*.v";
@@ -28,19 +28,25 @@ where
 }
 
 #[cfg(not(feature = "trace"))]
-pub(crate) fn symbol<'a>(t: &'a str) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, Symbol> {
+pub(crate) fn symbol<'a>(t: &'static str) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, Symbol> {
     move |s: Span<'a>| {
-        let (s, x) = map(ws(map(tag(t), into_locate)), |x| Symbol { nodes: x })(s)?;
+        let (s, x) = expected(
+            t,
+            map(ws(map(tag(t), into_locate)), |x| Symbol { nodes: x }),
+        )(s)?;
         Ok((s, x))
     }
 }
 
 #[cfg(feature = "trace")]
-pub(crate) fn symbol<'a>(t: &'a str) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, Symbol> {
+pub(crate) fn symbol<'a>(t: &'static str) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, Symbol> {
     move |s: Span<'a>| {
         let (depth, s) = nom_tracable::forward_trace(s, &format!("symbol(\"{}\")", t));
         let body = || {
-            let (s, x) = map(ws(map(tag(t), into_locate)), |x| Symbol { nodes: x })(s)?;
+            let (s, x) = expected(
+                t,
+                map(ws(map(tag(t), into_locate)), |x| Symbol { nodes: x }),
+            )(s)?;
             Ok((s, x))
         };
         let ret = body();
@@ -49,19 +55,29 @@ pub(crate) fn symbol<'a>(t: &'a str) -> impl FnMut(Span<'a>) -> IResult<Span<'a>
 }
 
 #[cfg(not(feature = "trace"))]
-pub(crate) fn symbol_exact<'a>(t: &'a str) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, Symbol> {
+pub(crate) fn symbol_exact<'a>(
+    t: &'static str,
+) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, Symbol> {
     move |s: Span<'a>| {
-        let (s, x) = map(no_ws(map(tag(t), into_locate)), |x| Symbol { nodes: x })(s)?;
+        let (s, x) = expected(
+            t,
+            map(no_ws(map(tag(t), into_locate)), |x| Symbol { nodes: x }),
+        )(s)?;
         Ok((s, x))
     }
 }
 
 #[cfg(feature = "trace")]
-pub(crate) fn symbol_exact<'a>(t: &'a str) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, Symbol> {
+pub(crate) fn symbol_exact<'a>(
+    t: &'static str,
+) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, Symbol> {
     move |s: Span<'a>| {
         let (depth, s) = nom_tracable::forward_trace(s, &format!("symbol(\"{}\")", t));
         let body = || {
-            let (s, x) = map(no_ws(map(tag(t), into_locate)), |x| Symbol { nodes: x })(s)?;
+            let (s, x) = expected(
+                t,
+                map(no_ws(map(tag(t), into_locate)), |x| Symbol { nodes: x }),
+            )(s)?;
             Ok((s, x))
         };
         let ret = body();
@@ -70,30 +86,36 @@ pub(crate) fn symbol_exact<'a>(t: &'a str) -> impl FnMut(Span<'a>) -> IResult<Sp
 }
 
 #[cfg(not(feature = "trace"))]
-pub(crate) fn keyword<'a>(t: &'a str) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, Keyword> {
+pub(crate) fn keyword<'a>(t: &'static str) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, Keyword> {
     move |s: Span<'a>| {
-        let (s, x) = map(
-            ws(alt((
-                all_consuming(map(tag(t), into_locate)),
-                terminated(map(tag(t), into_locate), peek(none_of(AZ09_))),
-            ))),
-            |x| Keyword { nodes: x },
+        let (s, x) = expected(
+            t,
+            map(
+                ws(alt((
+                    all_consuming(map(tag(t), into_locate)),
+                    terminated(map(tag(t), into_locate), peek(none_of(AZ09_))),
+                ))),
+                |x| Keyword { nodes: x },
+            ),
         )(s)?;
         Ok((s, x))
     }
 }
 
 #[cfg(feature = "trace")]
-pub(crate) fn keyword<'a>(t: &'a str) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, Keyword> {
+pub(crate) fn keyword<'a>(t: &'static str) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, Keyword> {
     move |s: Span<'a>| {
         let (depth, s) = nom_tracable::forward_trace(s, &format!("keyword(\"{}\")", t));
         let body = || {
-            let (s, x) = map(
-                ws(alt((
-                    all_consuming(map(tag(t), into_locate)),
-                    terminated(map(tag(t), into_locate), peek(none_of(AZ09_))),
-                ))),
-                |x| Keyword { nodes: x },
+            let (s, x) = expected(
+                t,
+                map(
+                    ws(alt((
+                        all_consuming(map(tag(t), into_locate)),
+                        terminated(map(tag(t), into_locate), peek(none_of(AZ09_))),
+                    ))),
+                    |x| Keyword { nodes: x },
+                ),
             )(s)?;
             Ok((s, x))
         };
@@ -285,6 +307,38 @@ where
     }
 }
 
+pub(crate) fn expected<'a, O, F>(
+    t: &'static str,
+    mut f: F,
+) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, O>
+where
+    F: FnMut(Span<'a>) -> IResult<Span<'a>, O>,
+{
+    move |s: Span<'a>| {
+        let ret = f(s);
+        if ret.is_err() {
+            push_expected(&s, t);
+        }
+        ret
+    }
+}
+
+pub(crate) fn rule<'a, O, F>(
+    name: &'static str,
+    f: F,
+) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, O>
+where
+    F: FnMut(Span<'a>) -> IResult<Span<'a>, O>,
+{
+    let mut f = context(name, f);
+    move |s: Span<'a>| {
+        begin_rule(name);
+        let ret = f(s);
+        end_rule();
+        ret
+    }
+}
+
 pub(crate) fn triple<'a, O1, O2, O3, F, G, H>(
     mut f: F,
     mut g: G,
@@ -308,7 +362,9 @@ where
 #[tracable_parser]
 #[packrat_parser]
 pub(crate) fn white_space(s: Span) -> IResult<Span, WhiteSpace> {
-    if in_directive() {
+    if error_text_len(&s).is_some() {
+        map(error_text, |x| WhiteSpace::ErrorText(Box::new(x)))(s)
+    } else if in_directive() {
         map(multispace1, |x: Span| {
             WhiteSpace::Space(Box::new(into_locate(x)))
         })(s)
@@ -328,6 +384,134 @@ pub(crate) fn white_space(s: Span) -> IResult<Span, WhiteSpace> {
     }
 }
 
+#[tracable_parser]
+pub(crate) fn error_text(s: Span) -> IResult<Span, ErrorText> {
+    if let Some(len) = error_text_len(&s) {
+        let (s, a) = take(len)(s)?;
+        Ok((
+            s,
+            ErrorText {
+                nodes: (into_locate(a),),
+            },
+        ))
+    } else {
+        Err(Err::Error(make_error(s, ErrorKind::Verify)))
+    }
+}
+
+thread_local!(
+    static ERROR_TEXTS: core::cell::RefCell<std::collections::HashMap<usize, usize>> = {
+        core::cell::RefCell::new(std::collections::HashMap::new())
+    }
+);
+
+// checked before `ERROR_TEXTS` so that normal parses skip the map lookup
+thread_local!(
+    static IN_RECOVERY: core::cell::Cell<bool> = const { core::cell::Cell::new(false) }
+);
+
+pub(crate) fn error_text_len(s: &Span) -> Option<usize> {
+    if !IN_RECOVERY.with(|x| x.get()) {
+        return None;
+    }
+    ERROR_TEXTS.with(|x| x.borrow().get(&s.location_offset()).copied())
+}
+
+pub(crate) fn set_error_texts(ranges: &[(usize, usize)]) {
+    ERROR_TEXTS.with(|x| {
+        let mut x = x.borrow_mut();
+        for (begin, end) in ranges {
+            if begin < end {
+                x.insert(*begin, end - begin);
+            }
+        }
+        IN_RECOVERY.with(|y| y.set(!x.is_empty()));
+    });
+}
+
+pub(crate) fn clear_error_texts() {
+    ERROR_TEXTS.with(|x| x.borrow_mut().clear());
+    IN_RECOVERY.with(|x| x.set(false));
+}
+
+// -----------------------------------------------------------------------------
+
+/// The tokens expected at the furthest failure position
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Expected {
+    pub pos: usize,
+    pub tokens: Vec<&'static str>,
+    pub rule: Option<&'static str>,
+}
+
+thread_local!(
+    static EXPECTED: core::cell::RefCell<Option<Expected>> = const {
+        core::cell::RefCell::new(None)
+    }
+);
+
+thread_local!(
+    static RULES: core::cell::RefCell<Vec<&'static str>> = const {
+        core::cell::RefCell::new(Vec::new())
+    }
+);
+
+// the position of `EXPECTED` checked before it is borrowed, because most failures are before it
+thread_local!(
+    static EXPECTED_POS: core::cell::Cell<usize> = const { core::cell::Cell::new(0) }
+);
+
+pub(crate) fn push_expected(s: &Span, t: &'static str) {
+    let pos = s.location_offset();
+    if pos < EXPECTED_POS.with(|x| x.get()) {
+        return;
+    }
+    let rule = RULES.with(|x| x.borrow().last().copied());
+    EXPECTED.with(|x| {
+        let mut x = x.borrow_mut();
+        match x.as_mut() {
+            Some(x) if x.pos == pos => {
+                if !x.tokens.contains(&t) {
+                    x.tokens.push(t);
+                }
+                if x.rule.is_none() {
+                    x.rule = rule;
+                }
+            }
+            Some(x) if x.pos > pos => (),
+            _ => {
+                EXPECTED_POS.with(|x| x.set(pos));
+                *x = Some(Expected {
+                    pos,
+                    tokens: vec![t],
+                    rule,
+                });
+            }
+        }
+    });
+}
+
+/// Returns the tokens expected at the furthest failure position of the last parse
+pub fn expected_tokens() -> Option<Expected> {
+    EXPECTED.with(|x| x.borrow().clone())
+}
+
+pub(crate) fn begin_rule(name: &'static str) {
+    RULES.with(|x| x.borrow_mut().push(name));
+}
+
+pub(crate) fn end_rule() {
+    RULES.with(|x| x.borrow_mut().pop());
+}
+
+pub(crate) fn clear_expected() {
+    EXPECTED.with(|x| *x.borrow_mut() = None);
+    EXPECTED_POS.with(|x| x.set(0));
+    RULES.with(|x| x.borrow_mut().clear());
+}
+
+// -----------------------------------------------------------------------------
+
 thread_local!(
     static IN_DIRECTIVE: core::cell::RefCell<Vec<()>> = {
         core::cell::RefCell::new(Vec::new())
@@ -423,6 +607,20 @@ pub(crate) fn clear_version() {
     });
 }
 
+/// Keyword versions of `` `begin_keywords `` which aren't ended yet
+#[derive(Clone, Debug, Default)]
+pub struct KeywordVersions(Vec<VersionSpecifier>);
+
+pub(crate) fn save_version() -> KeywordVersions {
+    CURRENT_VERSION.with(|current_version| KeywordVersions(current_version.borrow().clone()))
+}
+
+pub(crate) fn restore_version(versions: &KeywordVersions) {
+    CURRENT_VERSION.with(|current_version| {
+        *current_version.borrow_mut() = versions.0.clone();
+    });
+}
+
 // -----------------------------------------------------------------------------
 
 pub(crate) fn concat<'a>(a: Span<'a>, b: Span<'a>) -> Option<Span<'a>> {
@@ -0,0 +1,50 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use sv_parser_pp::preprocess::preprocess_str;
+
+// A header like UVM or generated register packages with many `ifdef blocks
+fn gen_ifdef_heavy(blocks: usize) -> String {
+    let mut ret = String::from("package p;\n");
+    for i in 0..blocks {
+        ret.push_str(&format!(
+            "`ifdef A{i}\n  logic [7:0] a{i};\n`elsif B{i}\n  logic [7:0] b{i};\n`else\n  `ifndef C{i}\n    logic [7:0] c{i};\n  `endif\n`endif\n",
+            i = i
+        ));
+    }
+    ret.push_str("endpackage\n");
+    ret
+}
+
+fn gen_benchmark_group(c: &mut Criterion, blocks: usize) {
+    let defines = HashMap::new();
+    let includes: Vec<PathBuf> = Vec::new();
+    let s = gen_ifdef_heavy(blocks);
+    let name = format!("ifdef_{}", blocks);
+    let mut group = c.benchmark_group(&name);
+    group.throughput(Throughput::Bytes(s.len() as u64));
+    group.bench_function(&name, |b| {
+        b.iter_with_large_drop(|| preprocess_str(&s, "", &defines, &includes, false, false, 0))
+    });
+    group.finish();
+}
+
+fn config() -> Criterion {
+    Criterion::default()
+        .sample_size(10)
+        .measurement_time(Duration::new(10, 0))
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    gen_benchmark_group(c, 1000);
+    gen_benchmark_group(c, 4000);
+}
+
+criterion_group! {
+    name = benches;
+    config = config();
+    targets = criterion_benchmark
+}
+
+criterion_main!(benches);
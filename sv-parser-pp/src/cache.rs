@@ -0,0 +1,153 @@
+use crate::encoding::Decoded;
+use crate::preprocess::{identifier, parse_pp_text};
+use crate::provider::FileProvider;
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+use sv_parser_error::{Error, Location};
+use sv_parser_syntaxtree::{
+    CompilerDirective, ConditionalCompilerDirective, IfdefCondition, PreprocessorText,
+    SourceDescription,
+};
+
+/// Parsed files kept through a session of preprocessing.
+/// A file is read again only if its modification time or length given by `FileProvider::stamp`
+/// is changed, and parsed again only if its text is changed.
+/// A file wrapped in an include guard isn't opened again while the guard macro is defined,
+/// so the files are assumed not to be changed in the session.
+#[derive(Debug, Default)]
+pub struct IncludeCache {
+    // decoded text of the files whose modification time and length are known
+    texts: RefCell<HashMap<PathBuf, ((SystemTime, u64), Rc<Decoded>)>>,
+    files: RefCell<HashMap<PathBuf, CachedFile>>,
+}
+
+#[derive(Debug)]
+struct CachedFile {
+    hash: u64,
+    pp_text: Rc<PreprocessorText>,
+    guard: Option<String>,
+    // whether comments are outside the include guard
+    comments: bool,
+}
+
+impl IncludeCache {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Get the macro of the include guard of `path` if it is cached
+    pub fn guard<T: AsRef<Path>>(&self, path: T) -> Option<String> {
+        self.files.borrow().get(path.as_ref())?.guard.clone()
+    }
+
+    /// Remove all files
+    pub fn clear(&self) {
+        self.texts.borrow_mut().clear();
+        self.files.borrow_mut().clear();
+    }
+
+    // Get the include guard of `path` if the output of the file is empty while it is defined.
+    // Comments outside the guard are output unless they are stripped.
+    pub(crate) fn skippable_guard(&self, path: &Path, strip_comments: bool) -> Option<String> {
+        let files = self.files.borrow();
+        let file = files.get(path)?;
+        if file.comments && !strip_comments {
+            None
+        } else {
+            file.guard.clone()
+        }
+    }
+
+    // Get the decoded text of `path` without reading it if it isn't changed
+    pub(crate) fn read(&self, path: &Path, files: &dyn FileProvider) -> io::Result<Rc<Decoded>> {
+        let stamp = files.stamp(path);
+        if let Some((x, decoded)) = self.texts.borrow().get(path) {
+            if stamp == Some(*x) {
+                return Ok(decoded.clone());
+            }
+        }
+
+        let decoded = Rc::new(files.read_decoded(path)?);
+        if let Some(stamp) = stamp {
+            self.texts
+                .borrow_mut()
+                .insert(PathBuf::from(path), (stamp, decoded.clone()));
+        } else {
+            self.texts.borrow_mut().remove(path);
+        }
+        Ok(decoded)
+    }
+
+    // Get the parsed text of `path` whose text is `s`
+    pub(crate) fn parse<F: Fn(usize, usize) -> Location>(
+        &self,
+        path: &Path,
+        s: &str,
+        locate_at: F,
+    ) -> Result<Rc<PreprocessorText>, Error> {
+        let mut hasher = DefaultHasher::new();
+        s.hash(&mut hasher);
+        let hash = hasher.finish();
+        if let Some(x) = self.files.borrow().get(path) {
+            if x.hash == hash {
+                return Ok(x.pp_text.clone());
+            }
+        }
+
+        let pp_text = Rc::new(parse_pp_text(s, locate_at)?);
+        let (guard, comments) = match include_guard(&pp_text, s) {
+            Some((guard, comments)) => (Some(guard), comments),
+            None => (None, false),
+        };
+        let file = CachedFile {
+            hash,
+            pp_text: pp_text.clone(),
+            guard,
+            comments,
+        };
+        self.files.borrow_mut().insert(PathBuf::from(path), file);
+        Ok(pp_text)
+    }
+}
+
+// Get the macro of `ifndef wrapping all text except whitespace and comments like
+//
+// `ifndef FOO_SVH
+// `define FOO_SVH
+// ...
+// `endif
+//
+// The text is empty except whitespace and comments if the macro is defined.
+// The flag is whether there are comments outside the guard.
+fn include_guard(pp_text: &PreprocessorText, s: &str) -> Option<(String, bool)> {
+    let mut ret = None;
+    let mut comments = false;
+    for x in &pp_text.nodes.0 {
+        match x {
+            SourceDescription::Comment(_) => comments = true,
+            SourceDescription::NotDirective(x) if x.nodes.0.str(s).trim().is_empty() => (),
+            SourceDescription::CompilerDirective(x) if ret.is_none() => {
+                if let CompilerDirective::ConditionalCompilerDirective(x) = &**x {
+                    if let ConditionalCompilerDirective::IfndefDirective(x) = &**x {
+                        let (_, _, ref ifid, _, ref elsif, ref elsebody, _, _) = x.nodes;
+                        if let IfdefCondition::TextMacroIdentifier(x) = ifid {
+                            if elsif.is_empty() && elsebody.is_none() {
+                                ret = identifier((&**x).into(), s);
+                                continue;
+                            }
+                        }
+                    }
+                }
+                return None;
+            }
+            _ => return None,
+        }
+    }
+    ret.map(|x| (x, comments))
+}
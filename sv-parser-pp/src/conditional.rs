@@ -0,0 +1,284 @@
+use crate::preprocess::{get_str, identifier, ifdef_condition, parse_pp_text, Defines, Source};
+use std::collections::HashSet;
+use std::hash::BuildHasher;
+use std::path::Path;
+use sv_parser_error::{Error, Location};
+use sv_parser_syntaxtree::{IfdefCondition, NodeEvent, RefNode};
+
+/// `` `ifdef `` or `` `ifndef `` directive with all of its branches
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Conditional {
+    /// Location of the directive up to `` `endif ``
+    pub location: Location,
+    pub branches: Vec<Branch>,
+}
+
+/// Branch of a conditional directive
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Branch {
+    /// `ifdef`, `ifndef`, `elsif` or `else`
+    pub directive: String,
+    /// Macro expression of the directive. It is empty for `else`.
+    pub condition: String,
+    /// Macros tested by `condition`
+    pub macros: Vec<String>,
+    /// Location of the body. It is `None` if the body is empty.
+    pub location: Option<Location>,
+    /// Conditionals nested in the body
+    pub children: Vec<Conditional>,
+}
+
+impl Conditional {
+    /// Get the macros tested by this directive and the nested ones in the order of appearance
+    pub fn macros(&self) -> Vec<String> {
+        let mut ret = Vec::new();
+        self.collect_macros(&mut ret);
+        ret
+    }
+
+    fn collect_macros(&self, ret: &mut Vec<String>) {
+        for branch in &self.branches {
+            for x in &branch.macros {
+                if !ret.contains(x) {
+                    ret.push(x.clone());
+                }
+            }
+            for x in &branch.children {
+                x.collect_macros(ret);
+            }
+        }
+    }
+}
+
+/// Get the conditional compilation structure of `s` without choosing branches.
+/// `` `include `` directives aren't followed.
+pub fn conditionals<T: AsRef<Path>>(s: &str, path: T) -> Result<Vec<Conditional>, Error> {
+    let source = Source::new(s, Vec::new());
+    let locate_at = |begin: usize, end: usize| source.location(path.as_ref(), begin, end);
+    let pp_text = parse_pp_text(s, locate_at)?;
+    Ok(collect((&pp_text).into(), s, locate_at))
+}
+
+/// Get the combinations of defining `macros` which take distinct branches of the
+/// conditional directives in `s`. Each combination is the defined state of each macro.
+///
+/// A macro which doesn't change the taken branches under the other macros is undefined.
+/// The macros of `pre_defines` which aren't in `macros` are defined in all combinations.
+/// `` `define `` and `` `undef `` directives aren't followed, and the macros which aren't
+/// tested in `s`, e.g. tested only in included files, are taken both ways.
+pub fn combinations<T: AsRef<Path>, V: BuildHasher>(
+    s: &str,
+    path: T,
+    pre_defines: &Defines<V>,
+    macros: &[&str],
+) -> Result<Vec<Vec<bool>>, Error> {
+    let source = Source::new(s, Vec::new());
+    let locate_at = |begin: usize, end: usize| source.location(path.as_ref(), begin, end);
+    let pp_text = parse_pp_text(s, locate_at)?;
+    let node: RefNode = (&pp_text).into();
+
+    let mut tested = HashSet::new();
+    for x in node.clone() {
+        if let RefNode::IfdefCondition(x) = x {
+            for y in x {
+                if let RefNode::TextMacroIdentifier(y) = y {
+                    tested.insert(identifier(y.into(), s).unwrap());
+                }
+            }
+        }
+    }
+
+    // Assign the macros on demand while walking the taken branches
+    let mut ret: Vec<Vec<bool>> = Vec::new();
+    let mut stack = vec![vec![None; macros.len()]];
+    while let Some(mut assigned) = stack.pop() {
+        let mut defines = Defines::new();
+        for k in pre_defines.keys() {
+            if !macros.contains(&k.as_str()) {
+                defines.insert(k.clone(), None);
+            }
+        }
+        for (x, y) in macros.iter().zip(&assigned) {
+            if *y == Some(true) {
+                defines.insert(String::from(*x), None);
+            }
+        }
+
+        let needed = needed_macro(node.clone(), s, &defines, macros, &assigned).or_else(|| {
+            (0..macros.len()).find(|i| assigned[*i].is_none() && !tested.contains(macros[*i]))
+        });
+        if let Some(i) = needed {
+            let mut x = assigned.clone();
+            x[i] = Some(true);
+            stack.push(x);
+            assigned[i] = Some(false);
+            stack.push(assigned);
+        } else {
+            ret.push(assigned.iter().map(|x| *x == Some(true)).collect());
+        }
+    }
+
+    // the order of counting up with the first macro as the lowest bit
+    ret.sort_by(|x, y| x.iter().rev().cmp(y.iter().rev()));
+    Ok(ret)
+}
+
+// Walk the branches taken with `defines` in `node`, and get the index of the first macro
+// of `macros` which is tested but isn't assigned yet
+fn needed_macro(
+    node: RefNode,
+    s: &str,
+    defines: &Defines,
+    macros: &[&str],
+    assigned: &[Option<bool>],
+) -> Option<usize> {
+    let mut depth = 0;
+    for event in node.into_iter().event() {
+        match event {
+            NodeEvent::Enter(x @ RefNode::IfdefDirective(_))
+            | NodeEvent::Enter(x @ RefNode::IfndefDirective(_)) => {
+                if depth == 0 {
+                    for (directive, condition, body) in branches(x).unwrap() {
+                        let taken = if let Some(condition) = condition {
+                            for y in condition {
+                                if let RefNode::TextMacroIdentifier(y) = y {
+                                    let id = identifier(y.into(), s).unwrap();
+                                    let i = macros.iter().position(|z| *z == id);
+                                    if let Some(i) = i.filter(|i| assigned[*i].is_none()) {
+                                        return Some(i);
+                                    }
+                                }
+                            }
+                            ifdef_condition(condition, s, defines) != (directive == "ifndef")
+                        } else {
+                            true
+                        };
+                        if taken {
+                            let ret = needed_macro(body, s, defines, macros, assigned);
+                            if ret.is_some() {
+                                return ret;
+                            }
+                            break;
+                        }
+                    }
+                }
+                depth += 1;
+            }
+            NodeEvent::Leave(RefNode::IfdefDirective(_))
+            | NodeEvent::Leave(RefNode::IfndefDirective(_)) => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+// Collect the outermost conditionals in `node`
+fn collect<F: Fn(usize, usize) -> Location + Copy>(
+    node: RefNode,
+    s: &str,
+    locate_at: F,
+) -> Vec<Conditional> {
+    let mut ret = Vec::new();
+    let mut depth = 0;
+    for event in node.into_iter().event() {
+        match event {
+            NodeEvent::Enter(x @ RefNode::IfdefDirective(_))
+            | NodeEvent::Enter(x @ RefNode::IfndefDirective(_)) => {
+                if depth == 0 {
+                    let branches = branches(x.clone()).unwrap();
+                    ret.push(conditional(x, branches, s, locate_at));
+                }
+                depth += 1;
+            }
+            NodeEvent::Leave(RefNode::IfdefDirective(_))
+            | NodeEvent::Leave(RefNode::IfndefDirective(_)) => depth -= 1,
+            _ => (),
+        }
+    }
+    ret
+}
+
+// Get the directive, the condition and the body of each branch of
+// an `` `ifdef `` or `` `ifndef `` directive
+fn branches<'a>(
+    node: RefNode<'a>,
+) -> Option<Vec<(&'static str, Option<&'a IfdefCondition>, RefNode<'a>)>> {
+    let mut ret = Vec::new();
+    let (elsif, elsebody) = match node {
+        RefNode::IfdefDirective(x) => {
+            let (_, _, ref ifid, ref ifbody, ref elsif, ref elsebody, _, _) = x.nodes;
+            ret.push(("ifdef", Some(ifid), ifbody.into()));
+            (elsif, elsebody)
+        }
+        RefNode::IfndefDirective(x) => {
+            let (_, _, ref ifid, ref ifbody, ref elsif, ref elsebody, _, _) = x.nodes;
+            ret.push(("ifndef", Some(ifid), ifbody.into()));
+            (elsif, elsebody)
+        }
+        _ => return None,
+    };
+    for (_, _, elsifid, elsifbody) in elsif {
+        ret.push(("elsif", Some(elsifid), elsifbody.into()));
+    }
+    if let Some((_, _, elsebody)) = elsebody {
+        ret.push(("else", None, elsebody.into()));
+    }
+    Some(ret)
+}
+
+fn conditional<F: Fn(usize, usize) -> Location + Copy>(
+    node: RefNode,
+    branches: Vec<(&str, Option<&IfdefCondition>, RefNode)>,
+    s: &str,
+    locate_at: F,
+) -> Conditional {
+    let (begin, end) = range(node).unwrap();
+    Conditional {
+        location: locate_at(begin, end),
+        branches: branches
+            .into_iter()
+            .map(|(directive, condition, body)| branch(directive, condition, body, s, locate_at))
+            .collect(),
+    }
+}
+
+fn branch<F: Fn(usize, usize) -> Location + Copy>(
+    directive: &str,
+    condition: Option<&IfdefCondition>,
+    body: RefNode,
+    s: &str,
+    locate_at: F,
+) -> Branch {
+    let mut macros = Vec::new();
+    if let Some(condition) = condition {
+        for node in condition {
+            if let RefNode::TextMacroIdentifier(x) = node {
+                let id = identifier(x.into(), s).unwrap();
+                if !macros.contains(&id) {
+                    macros.push(id);
+                }
+            }
+        }
+    }
+    Branch {
+        directive: String::from(directive),
+        condition: condition
+            .map(|x| String::from(get_str(x.into(), s).trim()))
+            .unwrap_or_default(),
+        macros,
+        location: range(body.clone()).map(|(begin, end)| locate_at(begin, end)),
+        children: collect(body, s, locate_at),
+    }
+}
+
+// Get the byte range covered by the locates in `node`
+fn range(node: RefNode) -> Option<(usize, usize)> {
+    let mut ret: Option<(usize, usize)> = None;
+    for x in node {
+        if let RefNode::Locate(x) = x {
+            let (begin, end) = ret.unwrap_or((x.offset, x.offset + x.len));
+            ret = Some((begin.min(x.offset), end.max(x.offset + x.len)));
+        }
+    }
+    ret
+}
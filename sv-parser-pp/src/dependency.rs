@@ -0,0 +1,160 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use sv_parser_error::Location;
+
+/// A file opened through an `` `include `` directive
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Dependency {
+    /// Resolved path of the included file
+    pub path: PathBuf,
+    /// Location of the `` `include `` directive
+    pub site: Location,
+}
+
+/// Render the dependencies of `source` as a GNU make rule like `gcc -MD`.
+/// If `phony` is true, an empty rule is added for each dependency like `gcc -MP`.
+pub fn make_rule<T: AsRef<Path>, U: AsRef<Path>>(
+    target: T,
+    source: U,
+    dependencies: &[Dependency],
+    phony: bool,
+) -> String {
+    let paths = unique_paths(dependencies);
+
+    let mut ret = format!(
+        "{}: \\\n  {}",
+        make_escape(target.as_ref()),
+        make_escape(source.as_ref())
+    );
+    for path in &paths {
+        ret.push_str(&format!(" \\\n  {}", make_escape(path)));
+    }
+    ret.push('\n');
+
+    if phony {
+        for path in paths {
+            ret.push_str(&format!("\n{}:\n", make_escape(path)));
+        }
+    }
+    ret
+}
+
+/// Render the dependencies of `source` as a JSON manifest
+pub fn json_manifest<T: AsRef<Path>>(source: T, dependencies: &[Dependency]) -> String {
+    let mut ret = format!(
+        "{{\n  \"source\": {},\n  \"includes\": [",
+        json_string(source.as_ref())
+    );
+    for (i, dependency) in dependencies.iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        ret.push_str(&format!(
+            "{}\n    {{\"path\": {}, \"site\": {{\"path\": {}, \"line\": {}, \"column\": {}}}}}",
+            separator,
+            json_string(&dependency.path),
+            json_string(&dependency.site.path),
+            dependency.site.line,
+            dependency.site.column,
+        ));
+    }
+    if !dependencies.is_empty() {
+        ret.push_str("\n  ");
+    }
+    ret.push_str("]\n}\n");
+    ret
+}
+
+fn unique_paths(dependencies: &[Dependency]) -> Vec<&Path> {
+    let mut found = HashSet::new();
+    dependencies
+        .iter()
+        .map(|x| x.path.as_path())
+        .filter(|x| found.insert(*x))
+        .collect()
+}
+
+fn make_escape(path: &Path) -> String {
+    let mut ret = String::new();
+    for c in path.to_string_lossy().chars() {
+        match c {
+            ' ' | '#' => {
+                ret.push('\\');
+                ret.push(c);
+            }
+            '$' => ret.push_str("$$"),
+            _ => ret.push(c),
+        }
+    }
+    ret
+}
+
+fn json_string(path: &Path) -> String {
+    let mut ret = String::from("\"");
+    for c in path.to_string_lossy().chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            '\t' => ret.push_str("\\t"),
+            c if (c as u32) < 0x20 => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dependency(path: &str, line: usize) -> Dependency {
+        Dependency {
+            path: PathBuf::from(path),
+            site: Location {
+                path: PathBuf::from("top.sv"),
+                begin: 0,
+                end: 0,
+                line,
+                column: 1,
+            },
+        }
+    }
+
+    #[test]
+    fn test_make_rule() {
+        let dependencies = [
+            dependency("inc/a.svh", 2),
+            dependency("my dir/b.svh", 3),
+            dependency("inc/a.svh", 4),
+        ];
+        assert_eq!(
+            make_rule("top.o", "top.sv", &dependencies, true),
+            "top.o: \\\n  top.sv \\\n  inc/a.svh \\\n  my\\ dir/b.svh\n\ninc/a.svh:\n\nmy\\ dir/b.svh:\n"
+        );
+        assert_eq!(
+            make_rule("top.o", "top.sv", &[], false),
+            "top.o: \\\n  top.sv\n"
+        );
+    }
+
+    #[test]
+    fn test_json_manifest() {
+        let dependencies = [dependency("inc/a.svh", 2), dependency("b\"c.svh", 3)];
+        assert_eq!(
+            json_manifest("top.sv", &dependencies),
+            r##"{
+  "source": "top.sv",
+  "includes": [
+    {"path": "inc/a.svh", "site": {"path": "top.sv", "line": 2, "column": 1}},
+    {"path": "b\"c.svh", "site": {"path": "top.sv", "line": 3, "column": 1}}
+  ]
+}
+"##
+        );
+        assert_eq!(
+            json_manifest("top.sv", &[]),
+            "{\n  \"source\": \"top.sv\",\n  \"includes\": []\n}\n"
+        );
+    }
+}
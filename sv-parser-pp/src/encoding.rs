@@ -0,0 +1,132 @@
+use std::io;
+use std::str;
+
+/// Text encoding of source files
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Encoding {
+    /// UTF-8. Invalid sequences are an error.
+    #[default]
+    Utf8,
+    /// UTF-8. Invalid sequences are replaced by U+FFFD.
+    Utf8Lossy,
+    /// ISO-8859-1
+    Latin1,
+    /// UTF-8 if the file is valid UTF-8, or ISO-8859-1 otherwise
+    Auto,
+}
+
+/// Text decoded from a source file with the byte offsets in the file
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Decoded {
+    pub text: String,
+    /// Offsets in `text` and the corresponding byte offsets in the file
+    /// at each point where the difference between them changes.
+    /// It is empty if they are identical.
+    pub offsets: Vec<(usize, usize)>,
+}
+
+impl Decoded {
+    /// Wrap `text` read as UTF-8. A leading byte order mark is removed.
+    pub fn new(text: String) -> Self {
+        match text.strip_prefix('\u{feff}') {
+            Some(x) => Decoded {
+                text: String::from(x),
+                offsets: vec![(0, 3)],
+            },
+            None => Decoded {
+                text,
+                offsets: Vec::new(),
+            },
+        }
+    }
+
+    /// Get the byte offset in the file of `pos` in the decoded text
+    pub fn raw_offset(&self, pos: usize) -> usize {
+        raw_offset(&self.offsets, pos)
+    }
+}
+
+pub(crate) fn raw_offset(offsets: &[(usize, usize)], pos: usize) -> usize {
+    let i = offsets.partition_point(|x| x.0 <= pos);
+    match i.checked_sub(1) {
+        Some(i) => offsets[i].1 + pos - offsets[i].0,
+        None => pos,
+    }
+}
+
+/// Decode `bytes` of a source file by `encoding`. A leading UTF-8 byte order mark is removed.
+pub fn decode(bytes: &[u8], encoding: Encoding) -> io::Result<Decoded> {
+    let mut ret = Decoded::default();
+    let mut raw = 0;
+    let mut bytes = bytes;
+    if let Some(x) = bytes.strip_prefix(b"\xef\xbb\xbf") {
+        bytes = x;
+        raw = 3;
+        ret.offsets.push((0, raw));
+    }
+
+    match encoding {
+        Encoding::Utf8 => {
+            let text =
+                str::from_utf8(bytes).map_err(|x| io::Error::new(io::ErrorKind::InvalidData, x))?;
+            ret.text.push_str(text);
+        }
+        Encoding::Auto if str::from_utf8(bytes).is_ok() => {
+            ret.text.push_str(str::from_utf8(bytes).unwrap());
+        }
+        Encoding::Utf8Lossy => loop {
+            match str::from_utf8(bytes) {
+                Ok(x) => {
+                    ret.text.push_str(x);
+                    break;
+                }
+                Err(e) => {
+                    let (valid, rest) = bytes.split_at(e.valid_up_to());
+                    let invalid = e.error_len().unwrap_or(rest.len());
+                    ret.text.push_str(str::from_utf8(valid).unwrap());
+                    ret.text.push('\u{fffd}');
+                    raw += valid.len() + invalid;
+                    ret.offsets.push((ret.text.len(), raw));
+                    bytes = &rest[invalid..];
+                }
+            }
+        },
+        Encoding::Latin1 | Encoding::Auto => {
+            for &x in bytes {
+                ret.text.push(char::from(x));
+                raw += 1;
+                if x >= 0x80 {
+                    ret.offsets.push((ret.text.len(), raw));
+                }
+            }
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let bytes = b"\xef\xbb\xbfa\xe9b\xffc";
+        assert!(decode(bytes, Encoding::Utf8).is_err());
+
+        let ret = decode(bytes, Encoding::Latin1).unwrap();
+        assert_eq!(ret.text, "a\u{e9}b\u{ff}c");
+        assert_eq!(ret.raw_offset(0), 3);
+        assert_eq!(ret.raw_offset(ret.text.find('b').unwrap()), 5);
+        assert_eq!(ret.raw_offset(ret.text.find('c').unwrap()), 7);
+        assert_eq!(decode(bytes, Encoding::Auto).unwrap(), ret);
+
+        let ret = decode(bytes, Encoding::Utf8Lossy).unwrap();
+        assert_eq!(ret.text, "a\u{fffd}b\u{fffd}c");
+        assert_eq!(ret.raw_offset(ret.text.find('c').unwrap()), 7);
+
+        let ret = decode("\u{feff}caf\u{e9}".as_bytes(), Encoding::Auto).unwrap();
+        assert_eq!(ret, Decoded::new(String::from("\u{feff}caf\u{e9}")));
+        assert_eq!(ret.text, "caf\u{e9}");
+        assert_eq!(ret.raw_offset(4), 7);
+    }
+}
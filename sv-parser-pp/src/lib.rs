@@ -1,4 +1,10 @@
 #![allow(clippy::type_complexity)]
 
+pub mod cache;
+pub mod conditional;
+pub mod dependency;
+pub mod encoding;
 pub mod preprocess;
+pub mod provider;
 pub mod range;
+pub mod warning;
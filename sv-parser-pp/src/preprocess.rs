@@ -1,17 +1,24 @@
+use crate::cache::IncludeCache;
+use crate::dependency::Dependency;
+use crate::encoding::{raw_offset, Decoded};
+use crate::provider::{DefaultIncludeResolver, FileProvider, FileSystem, IncludeResolver};
 use crate::range::Range;
+use crate::warning::Warning;
 use nom::combinator::all_consuming;
 use nom_greedyerror::error_position;
-use std::collections::{BTreeMap, HashMap};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
-use std::fs::File;
 use std::hash::BuildHasher;
-use std::io::{BufReader, Read};
+use std::mem::{discriminant, Discriminant};
 use std::path::{Path, PathBuf};
-use sv_parser_error::Error;
-use sv_parser_parser::{pp_parser, Span, SpanInfo};
+use std::rc::Rc;
+use std::sync::Arc;
+use sv_parser_error::{Error, Expansion, Location, SourceLocation};
+use sv_parser_parser::{expected_tokens, pp_parser, Span, SpanInfo};
 use sv_parser_syntaxtree::{
-    IncludeCompilerDirective, Locate, NodeEvent, RefNode, SourceDescription, TextMacroUsage,
-    WhiteSpace,
+    IfdefCondition, IfdefMacroExpression, IncludeCompilerDirective, Locate, NodeEvent,
+    PreprocessorText, RefNode, SourceDescription, TextMacroUsage, WhiteSpace,
 };
 use std::collections::hash_map::RandomState;
 
@@ -20,46 +27,429 @@ const RECURSIVE_LIMIT: usize = 64;
 #[derive(Debug)]
 pub struct PreprocessedText {
     text: String,
-    origins: BTreeMap<Range, Origin>,
+    // boxed to keep the frames of the recursive macro expansion small
+    map: Box<SourceMap>,
+    sources: HashMap<PathBuf, Source>,
+    dependencies: Vec<Dependency>,
+    warnings: Vec<Warning>,
+    inactive_regions: Vec<InactiveRegion>,
+    expansions: Vec<MacroExpansion>,
 }
 
+// Line starts of a file without its text, which is read again for snippets
 #[derive(Debug)]
-pub struct Origin {
-    range: Range,
-    origin: Option<(PathBuf, Range)>,
+pub(crate) struct Source {
+    lines: Vec<usize>,
+    // tabs and non-ASCII characters, which aren't one byte of one column, with their byte lengths
+    wide: Vec<(usize, usize)>,
+    line_map: Vec<LineDirective>,
+    // byte offsets in the file given by `Decoded`
+    offsets: Vec<(usize, usize)>,
+}
+
+// `` `line `` directive at the physical line `line`.
+// The next line is numbered `number` in `path`.
+#[derive(Debug)]
+struct LineDirective {
+    line: usize,
+    number: usize,
+    path: PathBuf,
+}
+
+impl Source {
+    pub(crate) fn new(text: &str, offsets: Vec<(usize, usize)>) -> Self {
+        Source {
+            lines: line_starts(text),
+            wide: text
+                .char_indices()
+                .filter(|(_, c)| *c == '\t' || !c.is_ascii())
+                .map(|(i, c)| (i, c.len_utf8()))
+                .collect(),
+            line_map: Vec::new(),
+            offsets,
+        }
+    }
+
+    pub(crate) fn location(&self, path: &Path, begin: usize, end: usize) -> Location {
+        let line = self.lines.partition_point(|x| *x <= begin);
+        let column = begin - self.lines[line - 1] + 1;
+        let (path, line) = remap(&self.line_map, line).unwrap_or((path, line));
+        Location {
+            path: PathBuf::from(path),
+            begin: raw_offset(&self.offsets, begin),
+            end: raw_offset(&self.offsets, end),
+            line,
+            column,
+        }
+    }
+
+    fn position(&self, pos: usize, tab_width: usize) -> Option<Position> {
+        let i = self.wide.partition_point(|x| x.0 < pos);
+        if matches!(i.checked_sub(1).map(|i| self.wide[i]), Some((x, len)) if pos < x + len) {
+            return None;
+        }
+        let line = self.lines.partition_point(|x| *x <= pos);
+        let begin = self.lines[line - 1];
+
+        // same as `Locate::char_column`
+        let mut char_column = 0;
+        let mut last = begin;
+        for (x, len) in &self.wide[self.wide.partition_point(|x| x.0 < begin)..i] {
+            char_column += x - last;
+            if *len == 1 && tab_width > 0 {
+                char_column += tab_width - char_column % tab_width;
+            } else {
+                char_column += 1;
+            }
+            last = x + len;
+        }
+        char_column += pos - last;
+
+        Some(Position {
+            offset: raw_offset(&self.offsets, pos),
+            line: remap(&self.line_map, line).map_or(line, |x| x.1),
+            column: pos - begin + 1,
+            char_column: char_column + 1,
+        })
+    }
+}
+
+/// Position in an original source file
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Position {
+    /// Byte offset in the physical file
+    pub offset: usize,
+    /// 1-origin line number after `` `line `` directives are applied
+    pub line: usize,
+    /// 1-origin byte column
+    pub column: usize,
+    /// 1-origin character column with tab expansion
+    pub char_column: usize,
+}
+
+/// Range in an original source file
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SourceRange {
+    /// File name given by the last `` `line `` directive before `begin`, or the physical file
+    pub path: PathBuf,
+    pub begin: Position,
+    pub end: Position,
+}
+
+/// Branch of a conditional compilation directive which isn't taken
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InactiveRegion {
+    /// Skipped text in the original source file
+    pub location: Location,
+    /// Directive controlling the branch: `ifdef`, `ifndef`, `elsif` or `else`
+    pub directive: String,
+    /// Macro expression of the directive. It is empty for `else`.
+    pub condition: String,
+}
+
+/// Text macro usage expanded by the preprocessor
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MacroExpansion {
+    pub name: String,
+    /// Text of the usage with actual arguments
+    pub usage: String,
+    /// Byte range of the usage in the original source.
+    /// A usage in a macro text points into the text of the enclosing definition.
+    pub site: Option<(PathBuf, (usize, usize))>,
+    /// Definition chosen for the usage, shared by the usages of the same definition
+    pub define: Arc<Define>,
+    /// Formal arguments bound to the actual arguments or the defaults in declaration order
+    pub arguments: Vec<MacroArgument>,
+    /// Byte range of the expanded text in the preprocessed text
+    pub range: (usize, usize),
+    /// Usages in the macro text expanded with this usage
+    pub children: Vec<MacroExpansion>,
+}
+
+/// Formal argument of a text macro bound by a usage
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MacroArgument {
+    pub name: String,
+    pub value: String,
+    /// `value` is the default text of the formal argument
+    pub default: bool,
+}
+
+impl MacroExpansion {
+    fn offset(&mut self, offset: usize) {
+        self.range.0 += offset;
+        self.range.1 += offset;
+        for x in &mut self.children {
+            x.offset(offset);
+        }
+    }
+}
+
+// Origins of the preprocessed text
+#[derive(Debug, Default)]
+struct SourceMap {
+    // runs of the text in the order of position
+    segments: Vec<Segment>,
+    // files referred by `segments`
+    paths: Vec<PathBuf>,
+    path_ids: HashMap<PathBuf, u32>,
+    // include and text macro expansion chains referred by `segments`
+    frames: Vec<Vec<OriginFrame>>,
+}
+
+impl SourceMap {
+    fn path_id(&mut self, path: &Path) -> u32 {
+        if let Some(x) = self.path_ids.get(path) {
+            return *x;
+        }
+        let id = self.paths.len() as u32;
+        self.paths.push(PathBuf::from(path));
+        self.path_ids.insert(PathBuf::from(path), id);
+        id
+    }
+
+    // Get the segment including `pos` of the text whose length is `len`
+    fn segment(&self, pos: usize, len: usize) -> Option<&Segment> {
+        if pos >= len {
+            return None;
+        }
+        let i = self.segments.partition_point(|x| x.begin <= pos);
+        self.segments.get(i.checked_sub(1)?)
+    }
+}
+
+// Run of the preprocessed text from contiguous text of a file.
+// It ends at the beginning of the next segment.
+#[derive(Clone, Copy, Debug)]
+struct Segment {
+    begin: usize,
+    // ID in `SourceMap::paths` and the position of `begin` in the file
+    origin: Option<(u32, usize)>,
+    // ID in `SourceMap::frames`
+    frames: u32,
+}
+
+// Origins of the text of a text macro expansion given by the substitution of the macro text.
+// Each run starts at a position of the text and continues from a position of a file.
+#[derive(Debug, Default)]
+struct TextMap {
+    runs: Vec<(usize, Option<(PathBuf, usize)>)>,
+}
+
+impl TextMap {
+    // Add the text from `pos` which continues from `origin`
+    fn push(&mut self, pos: usize, origin: Option<(&Path, usize)>) {
+        if let Some((begin, last)) = self.runs.last() {
+            let contiguous = match (last, origin) {
+                (Some((x, y)), Some((z, w))) => x == z && y + (pos - begin) == w,
+                (None, None) => true,
+                _ => false,
+            };
+            if contiguous {
+                return;
+            }
+        }
+        let origin = origin.map(|(x, y)| (PathBuf::from(x), y));
+        self.runs.push((pos, origin));
+    }
+
+    // Add the text from `pos` copied from `begin..end` of the text of `path` mapped by `map`
+    fn push_from(
+        &mut self,
+        pos: usize,
+        begin: usize,
+        end: usize,
+        path: &Path,
+        map: Option<&TextMap>,
+    ) {
+        if let Some(map) = map {
+            for (x, _, origin) in map.split(begin, end) {
+                self.push(pos + x - begin, origin);
+            }
+        } else if begin < end {
+            self.push(pos, Some((path, begin)));
+        }
+    }
+
+    // Remove the first `len` bytes of the text
+    fn trim_start(&mut self, len: usize) {
+        let i = self.runs.partition_point(|x| x.0 <= len);
+        if let Some(i) = i.checked_sub(1) {
+            self.runs.drain(..i);
+            let (begin, origin) = &mut self.runs[0];
+            if let Some((_, x)) = origin {
+                *x += len - *begin;
+            }
+            *begin = len;
+        }
+        for (begin, _) in &mut self.runs {
+            *begin -= len;
+        }
+    }
+
+    fn origin(&self, pos: usize) -> Option<(&Path, usize)> {
+        let i = self.runs.partition_point(|x| x.0 <= pos);
+        let (begin, origin) = self.runs.get(i.checked_sub(1)?)?;
+        origin.as_ref().map(|(x, y)| (x.as_path(), y + pos - begin))
+    }
+
+    // Split `begin..end` at the beginnings of the runs
+    fn split(&self, begin: usize, end: usize) -> Vec<(usize, usize, Option<(&Path, usize)>)> {
+        let mut ret = Vec::new();
+        let mut pos = begin;
+        let mut i = self.runs.partition_point(|x| x.0 <= begin);
+        while pos < end {
+            let next = self.runs.get(i).map_or(end, |x| x.0.min(end));
+            ret.push((pos, next, self.origin(pos)));
+            pos = next;
+            i += 1;
+        }
+        ret
+    }
+
+    // Get the range in the file of `begin..end`, which is empty at `begin`
+    // if the end isn't in the same file
+    fn range(&self, begin: usize, end: usize) -> Option<(PathBuf, (usize, usize))> {
+        let (path, x) = self.origin(begin)?;
+        let y = match self.origin(end.max(begin + 1) - 1) {
+            Some((z, y)) if z == path && y >= x && end > begin => y + 1,
+            _ => x,
+        };
+        Some((PathBuf::from(path), (x, y)))
+    }
+}
+
+// Get the origin of `pos` of the text whose origins are given by `map`, or of the file `path`
+fn text_origin<'a>(
+    path: &'a Path,
+    pos: usize,
+    map: Option<&'a TextMap>,
+) -> Option<(&'a Path, usize)> {
+    match map {
+        Some(map) => map.origin(pos),
+        None => Some((path, pos)),
+    }
+}
+
+// Get the range in the file of `begin..end` like `text_origin`
+fn text_range(
+    path: &Path,
+    begin: usize,
+    end: usize,
+    map: Option<&TextMap>,
+) -> Option<(PathBuf, (usize, usize))> {
+    match map {
+        Some(map) => map.range(begin, end),
+        None => Some((PathBuf::from(path), (begin, end))),
+    }
+}
+
+/// An include or a text macro expansion which produced a range of the preprocessed text
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum OriginFrame {
+    /// `` `include `` directive at `site`
+    Include {
+        site: Option<(PathBuf, (usize, usize))>,
+    },
+    /// Text macro usage at `site` expanded by the definition whose text is at `define`
+    Macro {
+        name: String,
+        site: Option<(PathBuf, (usize, usize))>,
+        define: Option<(PathBuf, (usize, usize))>,
+    },
+}
+
+impl OriginFrame {
+    fn site(&self) -> &Option<(PathBuf, (usize, usize))> {
+        match self {
+            OriginFrame::Include { site } => site,
+            OriginFrame::Macro { site, .. } => site,
+        }
+    }
 }
 
 impl PreprocessedText {
     fn new() -> Self {
         PreprocessedText {
             text: String::new(),
-            origins: BTreeMap::new(),
+            map: Box::new(SourceMap {
+                frames: vec![Vec::new()],
+                ..Default::default()
+            }),
+            sources: HashMap::new(),
+            dependencies: Vec::new(),
+            warnings: Vec::new(),
+            inactive_regions: Vec::new(),
+            expansions: Vec::new(),
         }
     }
 
     fn push<T: AsRef<Path>>(&mut self, s: &str, origin: Option<(T, Range)>) {
         let base = self.text.len();
         self.text.push_str(s);
+        if s.is_empty() {
+            return;
+        }
 
-        let origin = if let Some((origin_path, origin_range)) = origin {
-            let origin_path = PathBuf::from(origin_path.as_ref());
-            Some((origin_path, origin_range))
-        } else {
-            None
-        };
+        let map = &mut self.map;
+        let origin = origin.map(|(path, range)| (map.path_id(path.as_ref()), range.begin));
+
+        // extend the last segment if `s` follows it in the file
+        if let Some(last) = map.segments.last() {
+            let contiguous = match (last.origin, origin) {
+                (Some((x, y)), Some((z, w))) => x == z && y + (base - last.begin) == w,
+                (None, None) => true,
+                _ => false,
+            };
+            if contiguous && last.frames == 0 {
+                return;
+            }
+        }
+        map.segments.push(Segment {
+            begin: base,
+            origin,
+            frames: 0,
+        });
+    }
 
-        let range = Range::new(base, base + s.len());
-        let origin = Origin { range, origin };
-        self.origins.insert(range, origin);
+    // Push `s` at `pos` of the text whose origins are given by `map`, or of the file `path`
+    fn push_at(&mut self, s: &str, path: &Path, pos: usize, map: Option<&TextMap>) {
+        if let Some(map) = map {
+            for (begin, end, origin) in map.split(pos, pos + s.len()) {
+                let origin = origin.map(|(x, y)| (x, Range::new(y, y + end - begin)));
+                self.push(&s[begin - pos..end - pos], origin);
+            }
+        } else {
+            self.push(s, Some((path, Range::new(pos, pos + s.len()))));
+        }
     }
 
     fn merge(&mut self, other: PreprocessedText) {
         let base = self.text.len();
         self.text.push_str(&other.text);
-        for (mut range, mut origin) in other.origins {
-            range.offset(base);
-            origin.range.offset(base);
-            self.origins.insert(range, origin);
+        let map = &mut self.map;
+        let paths: Vec<_> = other.map.paths.iter().map(|x| map.path_id(x)).collect();
+        let frames = map.frames.len() as u32;
+        for mut segment in other.map.segments {
+            segment.begin += base;
+            segment.origin = segment.origin.map(|(id, pos)| (paths[id as usize], pos));
+            segment.frames += frames;
+            map.segments.push(segment);
+        }
+        map.frames.extend(other.map.frames);
+        self.sources.extend(other.sources);
+        self.dependencies.extend(other.dependencies);
+        self.inactive_regions.extend(other.inactive_regions);
+        for mut expansion in other.expansions {
+            expansion.offset(base);
+            self.expansions.push(expansion);
+        }
+    }
+
+    // Add `frame` as the outermost frame of all text
+    fn push_frame(&mut self, frame: OriginFrame) {
+        for frames in &mut self.map.frames {
+            frames.push(frame.clone());
         }
     }
 
@@ -67,18 +457,171 @@ impl PreprocessedText {
         &self.text
     }
 
-    pub fn origin(&self, pos: usize) -> Option<(&PathBuf, usize)> {
-        let origin = self.origins.get(&Range::new(pos, pos + 1));
-        if let Some(origin) = origin {
-            if let Some((ref origin_path, ref origin_range)) = origin.origin {
-                let ret_pos = pos - origin.range.begin + origin_range.begin;
-                Some((&origin_path, ret_pos))
+    /// Get the text with `` `line `` directives (IEEE 1800-2017 22.12) inserted where an included
+    /// file is entered or returned from, and where the text skips lines of the original file.
+    pub fn text_with_line_directives(&self) -> String {
+        let mut ret = String::new();
+        let mut current: Option<(&Path, usize)> = None;
+        let mut line = 0;
+        let map = &self.map;
+        for (i, segment) in map.segments.iter().enumerate() {
+            let end = map.segments.get(i + 1).map_or(self.text.len(), |x| x.begin);
+
+            // The position in the file at the include depth of the text.
+            // Text expanded from macros is placed at the outermost macro usage.
+            let frames = &map.frames[segment.frames as usize];
+            let depth = frames
+                .iter()
+                .filter(|x| matches!(x, OriginFrame::Include { .. }))
+                .count();
+            let k = frames
+                .iter()
+                .position(|x| matches!(x, OriginFrame::Include { .. }))
+                .unwrap_or(frames.len());
+            let site = if k == 0 {
+                segment
+                    .origin
+                    .map(|(id, pos)| (&map.paths[id as usize], pos))
             } else {
-                None
+                frames[k - 1]
+                    .site()
+                    .as_ref()
+                    .map(|(path, (begin, _))| (path, *begin))
+            };
+
+            let mut offset = 0;
+            for text in self.text[segment.begin..end].split_inclusive('\n') {
+                let first = offset + text.len() - text.trim_start().len();
+                offset += text.len();
+                if text.trim().is_empty() {
+                    ret.push_str(text);
+                    line += text.matches('\n').count();
+                    continue;
+                }
+
+                // the first token of the line
+                let position = site.and_then(|(path, pos)| {
+                    let source = self.sources.get(path)?;
+                    let pos = if k == 0 { pos + first } else { pos };
+                    Some((path.as_path(), source.position(pos, 1)?.line))
+                });
+
+                if let Some((path, source_line)) = position {
+                    let level = match current {
+                        None => Some(0),
+                        Some((_, x)) if x < depth => Some(1),
+                        Some((_, x)) if x > depth => Some(2),
+                        Some((x, _)) if x != path => Some(1),
+                        _ if k == 0 && line != source_line => Some(0),
+                        _ => None,
+                    };
+                    if let Some(level) = level {
+                        if !ret.is_empty() && !ret.ends_with('\n') {
+                            ret.push('\n');
+                        }
+                        let name = string_literal(path);
+                        ret.push_str(&format!("`line {} {} {}\n", source_line, name, level));
+                        current = Some((path, depth));
+                        line = source_line;
+                    }
+                }
+
+                ret.push_str(text);
+                line += text.matches('\n').count();
             }
-        } else {
-            None
         }
+        ret
+    }
+
+    /// Get the physical file and byte offset of `pos`.
+    /// `` `line `` directives don't change them; use `location` for the renamed file and line.
+    /// The offset is in the file before decoding, e.g. including a byte order mark.
+    pub fn origin(&self, pos: usize) -> Option<(&PathBuf, usize)> {
+        let (path, pos) = self.decoded_origin(pos)?;
+        let offsets = self.sources.get(path).map_or(&[][..], |x| &x.offsets);
+        Some((path, raw_offset(offsets, pos)))
+    }
+
+    // Get the physical file and offset of `pos` in the decoded text of the file
+    fn decoded_origin(&self, pos: usize) -> Option<(&PathBuf, usize)> {
+        let segment = self.map.segment(pos, self.text.len())?;
+        let (id, begin) = segment.origin?;
+        Some((&self.map.paths[id as usize], begin + pos - segment.begin))
+    }
+
+    /// Get the includes and text macro expansions which produced `pos`, innermost first
+    pub fn origin_frames(&self, pos: usize) -> Option<&[OriginFrame]> {
+        let segment = self.map.segment(pos, self.text.len())?;
+        Some(&self.map.frames[segment.frames as usize])
+    }
+
+    /// Get the line and column of `pos` in the origin file
+    pub fn location(&self, pos: usize) -> Option<Location> {
+        let (path, pos) = self.decoded_origin(pos)?;
+        let source = self.sources.get(path)?;
+        Some(source.location(path, pos, pos))
+    }
+
+    /// Get the location of `pos` with the include and text macro expansion chain
+    pub fn source_location(&self, pos: usize) -> Option<SourceLocation> {
+        let mut ret = SourceLocation::new(self.location(pos)?);
+        for frame in self.origin_frames(pos)? {
+            let expansion = match frame {
+                OriginFrame::Include { site } => {
+                    let (path, (begin, end)) = site.as_ref()?;
+                    let source = self.sources.get(path)?;
+                    Expansion::Include(source.location(path, *begin, *end))
+                }
+                OriginFrame::Macro { name, .. } => Expansion::Macro(name.clone()),
+            };
+            ret.expansions.push(expansion);
+        }
+        Some(ret)
+    }
+
+    /// Get the original file and positions of `begin..end` in the preprocessed text.
+    /// A tab in the original file advances the character column to the next multiple of `tab_width`.
+    pub fn source_range(&self, begin: usize, end: usize, tab_width: usize) -> Option<SourceRange> {
+        let (path, origin_begin) = self.decoded_origin(begin)?;
+        let origin_end = match self.decoded_origin(end.max(begin + 1) - 1) {
+            Some((x, pos)) if x == path && end > begin => pos + 1,
+            _ => origin_begin + (end - begin),
+        };
+        let source = self.sources.get(path)?;
+        let line = source.lines.partition_point(|x| *x <= origin_begin);
+        Some(SourceRange {
+            path: PathBuf::from(remap(&source.line_map, line).map_or(path.as_ref(), |x| x.0)),
+            begin: source.position(origin_begin, tab_width)?,
+            end: source.position(origin_end, tab_width)?,
+        })
+    }
+
+    /// Get the files opened through `` `include `` directives in the order of opening
+    pub fn dependencies(&self) -> &[Dependency] {
+        &self.dependencies
+    }
+
+    /// Get the warnings found in the compilation
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Get the outermost text macro expansions in the order of appearance
+    pub fn expansions(&self) -> &[MacroExpansion] {
+        &self.expansions
+    }
+
+    /// Get the branches skipped by `` `ifdef `` and `` `ifndef `` in the order of appearance.
+    /// Nested conditionals in a skipped branch are part of the outer region.
+    pub fn inactive_regions(&self) -> &[InactiveRegion] {
+        &self.inactive_regions
+    }
+
+    /// Read the original text of a preprocessed file through `files`.
+    /// The text isn't kept in `PreprocessedText` to save memory.
+    pub fn source<T: AsRef<Path>>(&self, path: T, files: &dyn FileProvider) -> Option<String> {
+        self.sources.get(path.as_ref())?;
+        files.read_decoded(path.as_ref()).ok().map(|x| x.text)
     }
 }
 
@@ -107,6 +650,80 @@ impl Define {
             text,
         }
     }
+
+    /// Parse a definition given on a command line like `WIDTH=8`,
+    /// `MAX(a,b)=((a)>(b)?(a):(b))` or `DEBUG` by the `` `define `` grammar.
+    /// Whitespace before `=` and `//` or `/*` outside string literals in the value are errors,
+    /// because the value isn't a text macro with comments.
+    pub fn parse(s: &str) -> Result<Define, Error> {
+        let name_end = s
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$'))
+            .unwrap_or(s.len());
+        let mut head_end = name_end;
+        if s[name_end..].starts_with('(') {
+            let mut depth = 0;
+            for (i, c) in s[name_end..].char_indices() {
+                match c {
+                    '(' => depth += 1,
+                    ')' if depth == 1 => {
+                        head_end = name_end + i + 1;
+                        break;
+                    }
+                    ')' => depth -= 1,
+                    _ => (),
+                }
+            }
+        }
+        let rest = &s[head_end..];
+        let src = match rest.strip_prefix('=') {
+            Some(text) if !has_comment(text) => format!(
+                "`define {} {}\n",
+                &s[..head_end],
+                text.replace('\n', "\\\n")
+            ),
+            None if rest.is_empty() => format!("`define {}\n", s),
+            _ => return Err(Error::DefineArg(String::from(s))),
+        };
+        let include_paths: [PathBuf; 0] = [];
+        let mut define = preprocess_str(&src, "", &Defines::new(), &include_paths, false, false, 0)
+            .ok()
+            .and_then(|(_, mut defines)| defines.remove(&s[..name_end])?)
+            .ok_or_else(|| Error::DefineArg(String::from(s)))?;
+        // the text isn't in any file
+        if let Some(ref mut text) = define.text {
+            text.text = String::from(text.text.trim_start());
+            text.origin = None;
+        }
+        Ok(define)
+    }
+}
+
+// Check `//` or `/*` outside string literals
+fn has_comment(s: &str) -> bool {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        if quoted {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => quoted = false,
+                _ => (),
+            }
+        } else if c == '"' {
+            quoted = true;
+        } else if s[i..].starts_with("//") || s[i..].starts_with("/*") {
+            return true;
+        }
+    }
+    false
+}
+
+/// Load the macros defined in `path` like a file of `` `define `` lines
+pub fn load_defines<T: AsRef<Path>>(path: T) -> Result<Defines, Error> {
+    let include_paths: [PathBuf; 0] = [];
+    let (_, defines) = preprocess(path, &Defines::new(), &include_paths, false, false)?;
+    Ok(defines)
 }
 
 impl DefineText {
@@ -124,50 +741,157 @@ pub fn preprocess<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
     strip_comments: bool,
     ignore_include: bool,
 ) -> Result<(PreprocessedText, Defines), Error> {
-    let f = File::open(path.as_ref()).map_err(|x| Error::File {
+    preprocess_with_provider(
+        path,
+        pre_defines,
+        include_paths,
+        strip_comments,
+        ignore_include,
+        &FileSystem,
+        &DefaultIncludeResolver,
+    )
+}
+
+/// Preprocess with the files given by `files`.
+/// The paths of `` `include `` directives are resolved by `resolver`.
+pub fn preprocess_with_provider<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
+    path: T,
+    pre_defines: &Defines<V>,
+    include_paths: &[U],
+    strip_comments: bool,
+    ignore_include: bool,
+    files: &dyn FileProvider,
+    resolver: &dyn IncludeResolver,
+) -> Result<(PreprocessedText, Defines), Error> {
+    preprocess_with_cache(
+        path,
+        pre_defines,
+        include_paths,
+        strip_comments,
+        ignore_include,
+        files,
+        resolver,
+        &IncludeCache::new(),
+    )
+}
+
+/// Preprocess with the parsed files kept in `cache`.
+/// It can be shared through the preprocessing of many files including the same headers.
+/// A repeated include of a file wrapped in an include guard which is defined is skipped,
+/// so the whitespace outside the guard isn't output
+/// and no `inactive_regions` are recorded for the file.
+/// It isn't skipped if comments are outside the guard and `strip_comments` is false.
+#[allow(clippy::too_many_arguments)]
+pub fn preprocess_with_cache<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
+    path: T,
+    pre_defines: &Defines<V>,
+    include_paths: &[U],
+    strip_comments: bool,
+    ignore_include: bool,
+    files: &dyn FileProvider,
+    resolver: &dyn IncludeResolver,
+    cache: &IncludeCache,
+) -> Result<(PreprocessedText, Defines), Error> {
+    let decoded = cache.read(path.as_ref(), files).map_err(|x| Error::File {
         source: x,
         path: PathBuf::from(path.as_ref()),
     })?;
-    let mut reader = BufReader::new(f);
-    let mut s = String::new();
-    reader.read_to_string(&mut s)?;
 
-    preprocess_str(
-        &s,
+    preprocess_decoded(
+        &decoded,
         path,
         pre_defines,
         include_paths,
         ignore_include,
         strip_comments,
         0,
+        files,
+        resolver,
+        cache,
     )
 }
 
+// Settings shared by the nested includes and text macro expansions
+struct Context<'a> {
+    files: &'a dyn FileProvider,
+    resolver: &'a dyn IncludeResolver,
+    cache: &'a IncludeCache,
+    macros: RefCell<MacroRecord>,
+    // the latest definition of each macro recorded in `MacroExpansion`
+    expanded: RefCell<HashMap<String, Arc<Define>>>,
+    // byte offsets of the decoded files which are going to be preprocessed
+    offsets: RefCell<HashMap<PathBuf, Vec<(usize, usize)>>>,
+}
+
+impl Context<'_> {
+    // Share `define` with the previous expansions of the same definition
+    fn expanded_define(&self, name: &str, define: &Define) -> Arc<Define> {
+        let mut expanded = self.expanded.borrow_mut();
+        match expanded.get(name) {
+            Some(x) if **x == *define => x.clone(),
+            _ => {
+                let x = Arc::new(define.clone());
+                expanded.insert(String::from(name), x.clone());
+                x
+            }
+        }
+    }
+}
+
+// Macro definitions and references through the compilation
+#[derive(Default)]
+struct MacroRecord {
+    defined: Vec<(String, Location)>,
+    // the latest definition of each macro
+    sites: HashMap<String, Location>,
+    used: HashSet<String>,
+    tested: Vec<(String, Location)>,
+    warnings: Vec<Warning>,
+}
+
+impl MacroRecord {
+    fn finish<V: BuildHasher>(self, pre_defines: &Defines<V>) -> Vec<Warning> {
+        let mut ret = self.warnings;
+        for (name, site) in self.defined {
+            if !self.used.contains(&name) {
+                ret.push(Warning::Unused { name, site });
+            }
+        }
+        for (name, site) in self.tested {
+            if !self.sites.contains_key(&name) && !pre_defines.contains_key(&name) {
+                ret.push(Warning::NeverDefined { name, site });
+            }
+        }
+        ret
+    }
+}
+
 struct SkipNodes<'a> {
-    nodes: Vec<RefNode<'a>>,
+    nodes: HashSet<(Discriminant<RefNode<'a>>, *const ())>,
 }
 
 impl<'a> SkipNodes<'a> {
     fn new() -> Self {
-        Self { nodes: vec![] }
+        Self {
+            nodes: HashSet::new(),
+        }
+    }
+
+    fn key(node: &RefNode<'a>) -> (Discriminant<RefNode<'a>>, *const ()) {
+        (discriminant(node), node.as_ptr())
     }
 
     fn push(&mut self, node: RefNode<'a>) {
         // if a node doesn't have locate, the node should be ignored
         // because the node can be identified in tree.
-        let mut have_locate = false;
-        for x in node.clone() {
-            if let RefNode::Locate(_) = x {
-                have_locate = true;
-            }
-        }
-        if have_locate {
-            self.nodes.push(node);
+        let key = Self::key(&node);
+        if node.into_iter().any(|x| matches!(x, RefNode::Locate(_))) {
+            self.nodes.insert(key);
         }
     }
 
     fn contains(&self, node: &RefNode<'a>) -> bool {
-        self.nodes.contains(node)
+        self.nodes.contains(&Self::key(node))
     }
 }
 
@@ -180,6 +904,121 @@ pub fn preprocess_str<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
     strip_comments: bool,
     resolve_depth: usize,
 ) -> Result<(PreprocessedText, Defines), Error> {
+    preprocess_str_with_provider(
+        s,
+        path,
+        pre_defines,
+        include_paths,
+        ignore_include,
+        strip_comments,
+        resolve_depth,
+        &FileSystem,
+        &DefaultIncludeResolver,
+    )
+}
+
+/// Preprocess `s` with the included files given by `files`.
+/// The paths of `` `include `` directives are resolved by `resolver`.
+#[allow(clippy::too_many_arguments)]
+pub fn preprocess_str_with_provider<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
+    s: &str,
+    path: T,
+    pre_defines: &Defines<V>,
+    include_paths: &[U],
+    ignore_include: bool,
+    strip_comments: bool,
+    resolve_depth: usize,
+    files: &dyn FileProvider,
+    resolver: &dyn IncludeResolver,
+) -> Result<(PreprocessedText, Defines), Error> {
+    preprocess_decoded(
+        &Decoded::new(String::from(s)),
+        path,
+        pre_defines,
+        include_paths,
+        ignore_include,
+        strip_comments,
+        resolve_depth,
+        files,
+        resolver,
+        &IncludeCache::new(),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn preprocess_decoded<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
+    decoded: &Decoded,
+    path: T,
+    pre_defines: &Defines<V>,
+    include_paths: &[U],
+    ignore_include: bool,
+    strip_comments: bool,
+    resolve_depth: usize,
+    files: &dyn FileProvider,
+    resolver: &dyn IncludeResolver,
+    cache: &IncludeCache,
+) -> Result<(PreprocessedText, Defines), Error> {
+    let ctx = Context {
+        files,
+        resolver,
+        cache,
+        macros: RefCell::new(MacroRecord::default()),
+        expanded: RefCell::new(HashMap::new()),
+        offsets: RefCell::new(HashMap::new()),
+    };
+    ctx.offsets
+        .borrow_mut()
+        .insert(PathBuf::from(path.as_ref()), decoded.offsets.clone());
+    let (mut ret, defines) = preprocess_str_impl(
+        &decoded.text,
+        path,
+        pre_defines,
+        include_paths,
+        ignore_include,
+        strip_comments,
+        resolve_depth,
+        None,
+        None,
+        &ctx,
+    )?;
+    ret.warnings = ctx.macros.into_inner().finish(pre_defines);
+    Ok((ret, defines))
+}
+
+// `site` is the location of the text macro usage if `s` is its expansion.
+// Errors in the expansion are reported at `site`, and the origins of `s` are given by `map`.
+#[allow(clippy::too_many_arguments)]
+fn preprocess_str_impl<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
+    s: &str,
+    path: T,
+    pre_defines: &Defines<V>,
+    include_paths: &[U],
+    ignore_include: bool,
+    strip_comments: bool,
+    resolve_depth: usize,
+    site: Option<&Location>,
+    map: Option<&TextMap>,
+    ctx: &Context,
+) -> Result<(PreprocessedText, Defines), Error> {
+    let source = if site.is_none() {
+        let offsets = ctx.offsets.borrow_mut().remove(path.as_ref());
+        Source::new(s, offsets.unwrap_or_default())
+    } else {
+        Source::new("", Vec::new())
+    };
+    let source = RefCell::new(source);
+    let locate_at = |begin: usize, end: usize| {
+        if let Some(site) = site {
+            site.clone()
+        } else {
+            source.borrow().location(path.as_ref(), begin, end)
+        }
+    };
+    let source_location = |x: &Locate| {
+        let location = locate_at(x.offset, x.offset + x.len);
+        Some(Box::new(SourceLocation::new(location)))
+    };
+
     let mut skip = false;
     let mut skip_nodes = SkipNodes::new();
     let mut defines = HashMap::new();
@@ -191,24 +1030,11 @@ pub fn preprocess_str<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
         defines.insert(k.clone(), (*v).clone());
     }
 
-    let span = Span::new_extra(&s, SpanInfo::default());
-    let (_, pp_text) = all_consuming(pp_parser)(span).map_err(|x| match x {
-        nom::Err::Incomplete(_) => Error::Parse(None),
-        nom::Err::Error(e) => {
-            if let Some(pos) = error_position(&e) {
-                Error::Parse(Some((PathBuf::from(path.as_ref()), pos)))
-            } else {
-                Error::Parse(None)
-            }
-        }
-        nom::Err::Failure(e) => {
-            if let Some(pos) = error_position(&e) {
-                Error::Parse(Some((PathBuf::from(path.as_ref()), pos)))
-            } else {
-                Error::Parse(None)
-            }
-        }
-    })?;
+    let pp_text = if site.is_none() {
+        ctx.cache.parse(path.as_ref(), s, locate_at)?
+    } else {
+        Rc::new(parse_pp_text(s, locate_at)?)
+    };
 
     let mut ret = PreprocessedText::new();
 
@@ -234,7 +1060,7 @@ pub fn preprocess_str<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
                 let locate: Locate = x.try_into().unwrap();
                 if let Some(last_include_line) = last_include_line {
                     if last_include_line == locate.line {
-                        return Err(Error::IncludeLine);
+                        return Err(Error::IncludeLine(source_location(&locate)));
                     }
                 }
             }
@@ -242,7 +1068,7 @@ pub fn preprocess_str<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
                 let locate: Locate = x.try_into().unwrap();
                 if let Some(last_include_line) = last_include_line {
                     if last_include_line == locate.line {
-                        return Err(Error::IncludeLine);
+                        return Err(Error::IncludeLine(source_location(&locate)));
                     }
                 }
             }
@@ -262,75 +1088,73 @@ pub fn preprocess_str<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
         match n {
             NodeEvent::Enter(RefNode::SourceDescriptionNotDirective(x)) => {
                 let locate: Locate = x.try_into().unwrap();
-                let range = Range::new(locate.offset, locate.offset + locate.len);
-                ret.push(locate.str(&s), Some((path.as_ref(), range)));
+                ret.push_at(locate.str(&s), path.as_ref(), locate.offset, map);
             }
             NodeEvent::Enter(RefNode::SourceDescription(SourceDescription::StringLiteral(x))) => {
                 let locate: Locate = (&**x).try_into().unwrap();
-                let range = Range::new(locate.offset, locate.offset + locate.len);
-                ret.push(locate.str(&s), Some((path.as_ref(), range)));
+                ret.push_at(locate.str(&s), path.as_ref(), locate.offset, map);
             }
             NodeEvent::Enter(RefNode::SourceDescription(SourceDescription::EscapedIdentifier(
                 x,
             ))) => {
                 let locate: Locate = (&**x).try_into().unwrap();
-                let range = Range::new(locate.offset, locate.offset + locate.len);
-                ret.push(locate.str(&s), Some((path.as_ref(), range)));
+                ret.push_at(locate.str(&s), path.as_ref(), locate.offset, map);
             }
             NodeEvent::Enter(RefNode::ResetallCompilerDirective(x)) => {
                 let locate: Locate = x.try_into().unwrap();
-                let range = Range::new(locate.offset, locate.offset + locate.len);
-                ret.push(locate.str(&s), Some((path.as_ref(), range)));
+                ret.push_at(locate.str(&s), path.as_ref(), locate.offset, map);
             }
             NodeEvent::Enter(RefNode::TimescaleCompilerDirective(x)) => {
                 let locate: Locate = x.try_into().unwrap();
-                let range = Range::new(locate.offset, locate.offset + locate.len);
-                ret.push(locate.str(&s), Some((path.as_ref(), range)));
+                ret.push_at(locate.str(&s), path.as_ref(), locate.offset, map);
             }
             NodeEvent::Enter(RefNode::DefaultNettypeCompilerDirective(x)) => {
                 let locate: Locate = x.try_into().unwrap();
-                let range = Range::new(locate.offset, locate.offset + locate.len);
-                ret.push(locate.str(&s), Some((path.as_ref(), range)));
+                ret.push_at(locate.str(&s), path.as_ref(), locate.offset, map);
             }
             NodeEvent::Enter(RefNode::UnconnectedDriveCompilerDirective(x)) => {
                 let locate: Locate = x.try_into().unwrap();
-                let range = Range::new(locate.offset, locate.offset + locate.len);
-                ret.push(locate.str(&s), Some((path.as_ref(), range)));
+                ret.push_at(locate.str(&s), path.as_ref(), locate.offset, map);
             }
             NodeEvent::Enter(RefNode::NounconnectedDriveCompilerDirective(x)) => {
                 let locate: Locate = x.try_into().unwrap();
-                let range = Range::new(locate.offset, locate.offset + locate.len);
-                ret.push(locate.str(&s), Some((path.as_ref(), range)));
+                ret.push_at(locate.str(&s), path.as_ref(), locate.offset, map);
             }
             NodeEvent::Enter(RefNode::CelldefineDriveCompilerDirective(x)) => {
                 let locate: Locate = x.try_into().unwrap();
-                let range = Range::new(locate.offset, locate.offset + locate.len);
-                ret.push(locate.str(&s), Some((path.as_ref(), range)));
+                ret.push_at(locate.str(&s), path.as_ref(), locate.offset, map);
             }
             NodeEvent::Enter(RefNode::EndcelldefineDriveCompilerDirective(x)) => {
                 let locate: Locate = x.try_into().unwrap();
-                let range = Range::new(locate.offset, locate.offset + locate.len);
-                ret.push(locate.str(&s), Some((path.as_ref(), range)));
+                ret.push_at(locate.str(&s), path.as_ref(), locate.offset, map);
             }
             NodeEvent::Enter(RefNode::Pragma(x)) => {
                 let locate: Locate = x.try_into().unwrap();
-                let range = Range::new(locate.offset, locate.offset + locate.len);
-                ret.push(locate.str(&s), Some((path.as_ref(), range)));
+                ret.push_at(locate.str(&s), path.as_ref(), locate.offset, map);
             }
             NodeEvent::Enter(RefNode::LineCompilerDirective(x)) => {
                 let locate: Locate = x.try_into().unwrap();
-                let range = Range::new(locate.offset, locate.offset + locate.len);
-                ret.push(locate.str(&s), Some((path.as_ref(), range)));
+                if site.is_none() {
+                    let (_, _, ref number, ref filename, _) = x.nodes;
+                    let number = get_str(number.into(), s).trim().parse();
+                    let filename = filename.nodes.0.str(s).trim_matches('"');
+                    if let Ok(number) = number {
+                        source.borrow_mut().line_map.push(LineDirective {
+                            line: locate.line as usize,
+                            number,
+                            path: PathBuf::from(filename),
+                        });
+                    }
+                }
+                ret.push_at(locate.str(&s), path.as_ref(), locate.offset, map);
             }
             NodeEvent::Enter(RefNode::KeywordsDirective(x)) => {
                 let locate: Locate = x.try_into().unwrap();
-                let range = Range::new(locate.offset, locate.offset + locate.len);
-                ret.push(locate.str(&s), Some((path.as_ref(), range)));
+                ret.push_at(locate.str(&s), path.as_ref(), locate.offset, map);
             }
             NodeEvent::Enter(RefNode::EndkeywordsDirective(x)) => {
                 let locate: Locate = x.try_into().unwrap();
-                let range = Range::new(locate.offset, locate.offset + locate.len);
-                ret.push(locate.str(&s), Some((path.as_ref(), range)));
+                ret.push_at(locate.str(&s), path.as_ref(), locate.offset, map);
             }
             NodeEvent::Enter(RefNode::UndefineCompilerDirective(x)) => {
                 skip_nodes.push(x.into());
@@ -338,7 +1162,12 @@ pub fn preprocess_str<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
 
                 let (_, _, ref name) = x.nodes;
                 let id = identifier((&name.nodes.0).into(), &s).unwrap();
-                defines.remove(&id);
+                if defines.remove(&id).is_none() {
+                    let locate: Locate = x.try_into().unwrap();
+                    let site = locate_at(locate.offset, locate.offset + locate.len);
+                    let warning = Warning::UndefNotDefined { name: id, site };
+                    ctx.macros.borrow_mut().warnings.push(warning);
+                }
             }
             NodeEvent::Enter(RefNode::UndefineallCompilerDirective(x)) => {
                 skip_nodes.push(x.into());
@@ -350,27 +1179,43 @@ pub fn preprocess_str<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
                 let (_, ref keyword, ref ifid, ref ifbody, ref elsif, ref elsebody, _, _) = x.nodes;
                 skip_nodes.push(keyword.into());
                 skip_nodes.push(ifid.into());
+                test_macros(ifid, s, locate_at, ctx);
 
-                let ifid = identifier(ifid.into(), &s).unwrap();
                 let mut hit = false;
-                if defines.contains_key(&ifid) {
+                if ifdef_condition(ifid, &s, &defines) {
                     hit = true;
                 } else {
                     skip_nodes.push(ifbody.into());
+                    let region = inactive_region(
+                        ifbody.into(),
+                        "ifdef",
+                        Some(ifid.into()),
+                        s,
+                        site,
+                        locate_at,
+                    );
+                    ret.inactive_regions.extend(region);
                 }
 
                 for x in elsif {
                     let (_, ref keyword, ref elsifid, ref elsifbody) = x;
                     skip_nodes.push(keyword.into());
                     skip_nodes.push(elsifid.into());
+                    test_macros(elsifid, s, locate_at, ctx);
 
-                    let elsifid = identifier(elsifid.into(), &s).unwrap();
-                    if hit {
+                    if hit || !ifdef_condition(elsifid, &s, &defines) {
                         skip_nodes.push(elsifbody.into());
-                    } else if defines.contains_key(&elsifid) {
-                        hit = true;
+                        let region = inactive_region(
+                            elsifbody.into(),
+                            "elsif",
+                            Some(elsifid.into()),
+                            s,
+                            site,
+                            locate_at,
+                        );
+                        ret.inactive_regions.extend(region);
                     } else {
-                        skip_nodes.push(elsifbody.into());
+                        hit = true;
                     }
                 }
 
@@ -379,46 +1224,63 @@ pub fn preprocess_str<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
                     skip_nodes.push(keyword.into());
                     if hit {
                         skip_nodes.push(elsebody.into());
+                        let region =
+                            inactive_region(elsebody.into(), "else", None, s, site, locate_at);
+                        ret.inactive_regions.extend(region);
                     }
                 }
             }
             NodeEvent::Enter(RefNode::WhiteSpace(x)) if !strip_comments => {
                 if let WhiteSpace::Space(_) = x {
                     let locate: Locate = x.try_into().unwrap();
-                    let range = Range::new(locate.offset + locate.len, locate.offset + locate.len);
-                    ret.push(locate.str(&s), Some((path.as_ref(), range)));
+                    ret.push_at(locate.str(&s), path.as_ref(), locate.offset, map);
                 }
             }
             NodeEvent::Enter(RefNode::Comment(x)) if !strip_comments => {
                 let locate: Locate = x.try_into().unwrap();
-                let range = Range::new(locate.offset, locate.offset + locate.len);
-                ret.push(locate.str(&s), Some((path.as_ref(), range)));
+                ret.push_at(locate.str(&s), path.as_ref(), locate.offset, map);
             }
             NodeEvent::Enter(RefNode::IfndefDirective(x)) => {
                 let (_, ref keyword, ref ifid, ref ifbody, ref elsif, ref elsebody, _, _) = x.nodes;
                 skip_nodes.push(keyword.into());
                 skip_nodes.push(ifid.into());
+                test_macros(ifid, s, locate_at, ctx);
 
-                let ifid = identifier(ifid.into(), &s).unwrap();
                 let mut hit = false;
-                if !defines.contains_key(&ifid) {
+                if !ifdef_condition(ifid, &s, &defines) {
                     hit = true;
                 } else {
                     skip_nodes.push(ifbody.into());
+                    let region = inactive_region(
+                        ifbody.into(),
+                        "ifndef",
+                        Some(ifid.into()),
+                        s,
+                        site,
+                        locate_at,
+                    );
+                    ret.inactive_regions.extend(region);
                 }
 
                 for x in elsif {
                     let (_, ref keyword, ref elsifid, ref elsifbody) = x;
                     skip_nodes.push(keyword.into());
                     skip_nodes.push(elsifid.into());
+                    test_macros(elsifid, s, locate_at, ctx);
 
-                    let elsifid = identifier(elsifid.into(), &s).unwrap();
-                    if hit {
+                    if hit || !ifdef_condition(elsifid, &s, &defines) {
                         skip_nodes.push(elsifbody.into());
-                    } else if defines.contains_key(&elsifid) {
-                        hit = true;
+                        let region = inactive_region(
+                            elsifbody.into(),
+                            "elsif",
+                            Some(elsifid.into()),
+                            s,
+                            site,
+                            locate_at,
+                        );
+                        ret.inactive_regions.extend(region);
                     } else {
-                        skip_nodes.push(elsifbody.into());
+                        hit = true;
                     }
                 }
 
@@ -427,6 +1289,9 @@ pub fn preprocess_str<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
                     skip_nodes.push(keyword.into());
                     if hit {
                         skip_nodes.push(elsebody.into());
+                        let region =
+                            inactive_region(elsebody.into(), "else", None, s, site, locate_at);
+                        ret.inactive_regions.extend(region);
                     }
                 }
             }
@@ -461,11 +1326,12 @@ pub fn preprocess_str<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
 
                 let define_text = if let Some(text) = text {
                     let text: Locate = text.try_into().unwrap();
-                    let range = Range::new(text.offset, text.offset + text.len);
+                    let origin =
+                        text_range(path.as_ref(), text.offset, text.offset + text.len, map);
                     let text = String::from(text.str(&s));
                     Some(DefineText {
                         text,
-                        origin: Some((PathBuf::from(path.as_ref()), range)),
+                        origin: origin.map(|(x, (y, z))| (x, Range::new(y, z))),
                     })
                 } else {
                     None
@@ -477,12 +1343,28 @@ pub fn preprocess_str<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
                     text: define_text,
                 };
 
+                let locate: Locate = x.try_into().unwrap();
+                let site = locate_at(locate.offset, locate.offset + locate.len);
+                let mut macros = ctx.macros.borrow_mut();
+                if let Some(Some(previous)) = defines.get(&id) {
+                    let body = |x: &Define| x.text.as_ref().map(|x| String::from(x.text.trim()));
+                    if previous.arguments != define.arguments || body(previous) != body(&define) {
+                        let previous = macros.sites.get(&id).cloned();
+                        macros.warnings.push(Warning::Redefined {
+                            name: id.clone(),
+                            site: site.clone(),
+                            previous,
+                        });
+                    }
+                }
+                macros.defined.push((id.clone(), site.clone()));
+                macros.sites.insert(id.clone(), site);
+                drop(macros);
+
                 defines.insert(id, Some(define));
 
                 // Keep TextMacroDefinition after preprocess
-                let locate: Locate = x.try_into().unwrap();
-                let range = Range::new(locate.offset, locate.offset + locate.len);
-                ret.push(locate.str(&s), Some((path.as_ref(), range)));
+                ret.push_at(locate.str(&s), path.as_ref(), locate.offset, map);
             }
             NodeEvent::Enter(RefNode::IncludeCompilerDirective(x)) if !ignore_include => {
                 skip_nodes.push(x.into());
@@ -493,10 +1375,15 @@ pub fn preprocess_str<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
 
                 if let Some(last_item_line) = last_item_line {
                     if last_item_line == locate.line {
-                        return Err(Error::IncludeLine);
+                        return Err(Error::IncludeLine(source_location(&locate)));
                     }
                 }
 
+                let includer = PathBuf::from(path.as_ref());
+                let include_frame = OriginFrame::Include {
+                    site: text_range(&includer, locate.offset, locate.offset + locate.len, map),
+                };
+
                 let mut path = match x {
                     IncludeCompilerDirective::DoubleQuote(x) => {
                         let (_, ref keyword, ref literal) = x.nodes;
@@ -518,8 +1405,10 @@ pub fn preprocess_str<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
                         let (_, ref keyword, ref x) = x.nodes;
                         skip_nodes.push(keyword.into());
                         skip_nodes.push(x.into());
+                        use_macro(x, s, ctx);
 
-                        if let Some((p, _, _)) = resolve_text_macro_usage(
+                        let locate: Locate = x.try_into().unwrap();
+                        if let Some((p, _)) = resolve_text_macro_usage(
                             x,
                             s,
                             path.as_ref(),
@@ -527,37 +1416,81 @@ pub fn preprocess_str<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
                             include_paths,
                             strip_comments,
                             resolve_depth + 1,
+                            &locate_at(locate.offset, locate.offset + locate.len),
+                            map,
+                            ctx,
                         )? {
-                            let p = p.trim().trim_matches('"');
+                            let p = p.text().trim().trim_matches('"');
                             PathBuf::from(p)
                         } else {
                             PathBuf::from("")
                         }
                     }
                 };
-                if path.is_relative() && !path.exists() {
-                    for include_path in include_paths {
-                        let new_path = include_path.as_ref().join(&path);
-                        if new_path.exists() {
-                            path = new_path;
-                            break;
-                        }
+                let search_paths: Vec<PathBuf> = include_paths
+                    .iter()
+                    .map(|x| PathBuf::from(x.as_ref()))
+                    .collect();
+                if let Some(x) = ctx
+                    .resolver
+                    .resolve(&path, &includer, &search_paths, ctx.files)
+                {
+                    path = x;
+                }
+                let site = locate_at(locate.offset, locate.offset + locate.len);
+                let dependency = Dependency {
+                    path: path.clone(),
+                    site: site.clone(),
+                };
+
+                // the file is empty if it is wrapped in an include guard which is defined
+                if let Some(guard) = ctx.cache.skippable_guard(&path, strip_comments) {
+                    if defines.contains_key(&guard) {
+                        ret.dependencies.push(dependency);
+                        continue;
                     }
                 }
-                let (include, new_defines) =
-                    preprocess(path, &defines, include_paths, strip_comments, false).map_err(
-                        |x| Error::Include {
-                            source: Box::new(x),
-                        },
-                    )?;
+
+                let (mut include, new_defines) = ctx
+                    .cache
+                    .read(&path, ctx.files)
+                    .map_err(|x| Error::File {
+                        source: x,
+                        path: path.clone(),
+                    })
+                    .and_then(|decoded| {
+                        ctx.offsets
+                            .borrow_mut()
+                            .insert(path.clone(), decoded.offsets.clone());
+                        preprocess_str_impl(
+                            &decoded.text,
+                            &path,
+                            &defines,
+                            include_paths,
+                            false,
+                            strip_comments,
+                            0,
+                            None,
+                            None,
+                            ctx,
+                        )
+                    })
+                    .map_err(|x| Error::Include {
+                        source: Box::new(x.expanded(Expansion::Include(site.clone()))),
+                        location: Some(Box::new(SourceLocation::new(site))),
+                    })?;
+                include.push_frame(include_frame);
                 defines = new_defines;
+                ret.dependencies.push(dependency);
                 ret.merge(include);
             }
             NodeEvent::Enter(RefNode::TextMacroUsage(x)) => {
                 skip_nodes.push(x.into());
                 skip = true;
+                use_macro(x, s, ctx);
 
-                if let Some((text, origin, new_defines)) = resolve_text_macro_usage(
+                let locate: Locate = x.try_into().unwrap();
+                if let Some((text, new_defines)) = resolve_text_macro_usage(
                     x,
                     s,
                     path.as_ref(),
@@ -565,8 +1498,11 @@ pub fn preprocess_str<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
                     include_paths,
                     strip_comments,
                     resolve_depth + 1,
+                    &locate_at(locate.offset, locate.offset + locate.len),
+                    map,
+                    ctx,
                 )? {
-                    ret.push(&text, origin);
+                    ret.merge(text);
                     defines = new_defines;
                 }
             }
@@ -574,29 +1510,111 @@ pub fn preprocess_str<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
                 skip_nodes.push(x.into());
                 skip = true;
 
-                let (_, ref x) = x.nodes;
-                let locate: Locate = x.try_into().unwrap();
-                let x = locate.str(s);
-                if x.starts_with("__FILE__") {
-                    ret.push::<PathBuf>(
-                        &x.replace(
-                            "__FILE__",
-                            &format!("\"{}\"", path.as_ref().to_string_lossy()),
-                        ),
-                        None,
-                    );
-                } else if x.starts_with("__LINE__") {
-                    ret.push::<PathBuf>(&x.replace("__LINE__", &format!("{}", locate.line)), None);
-                }
+                let (_, ref keyword) = x.nodes;
+                let locate: Locate = keyword.try_into().unwrap();
+                // IEEE 1800-2017 22.13: the position in the original file after `line directives.
+                // It is the outermost text macro usage in macro text.
+                let location = locate_at(locate.offset, locate.offset + locate.len);
+                // the keyword is followed by whitespace
+                let (name, rest) = locate.str(s).split_at("__FILE__".len());
+                let value = if name == "__FILE__" {
+                    string_literal(&location.path)
+                } else {
+                    format!("{}", location.line)
+                };
+                let origin = text_origin(path.as_ref(), locate.offset, map);
+                let origin = origin.map(|(x, y)| (x, Range::new(y, y + locate.len)));
+                ret.push(&format!("{}{}", value, rest), origin);
             }
             _ => (),
         }
     }
 
+    if site.is_none() {
+        ret.sources
+            .insert(PathBuf::from(path.as_ref()), source.into_inner());
+    }
+
     Ok((ret, defines))
 }
 
-fn identifier(node: RefNode, s: &str) -> Option<String> {
+// Quote `path` as a string literal
+fn string_literal(path: &Path) -> String {
+    let name = path
+        .to_string_lossy()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"");
+    format!("\"{}\"", name)
+}
+
+// Get the region of the skipped branch `body` if it is in a physical file
+fn inactive_region<F: Fn(usize, usize) -> Location>(
+    body: RefNode,
+    directive: &str,
+    condition: Option<RefNode>,
+    s: &str,
+    site: Option<&Location>,
+    locate_at: F,
+) -> Option<InactiveRegion> {
+    if site.is_some() {
+        return None;
+    }
+    let mut range: Option<(usize, usize)> = None;
+    for x in body {
+        if let RefNode::Locate(x) = x {
+            let (begin, end) = range.unwrap_or((x.offset, x.offset + x.len));
+            range = Some((begin.min(x.offset), end.max(x.offset + x.len)));
+        }
+    }
+    let (begin, end) = range?;
+    Some(InactiveRegion {
+        location: locate_at(begin, end),
+        directive: String::from(directive),
+        condition: condition
+            .map(|x| String::from(get_str(x, s).trim()))
+            .unwrap_or_default(),
+    })
+}
+
+// Parse `s` by the preprocessor grammar
+pub(crate) fn parse_pp_text<F: Fn(usize, usize) -> Location>(
+    s: &str,
+    locate_at: F,
+) -> Result<PreprocessorText, Error> {
+    let span = Span::new_extra(s, SpanInfo::default());
+    let (_, pp_text) = all_consuming(pp_parser)(span).map_err(|x| {
+        let pos = match x {
+            nom::Err::Incomplete(_) => None,
+            nom::Err::Error(e) => error_position(&e),
+            nom::Err::Failure(e) => error_position(&e),
+        };
+        let (expected, rule) = match expected_tokens() {
+            Some(x) if Some(x.pos) == pos => (x.tokens, x.rule),
+            _ => (Vec::new(), None),
+        };
+        Error::Parse {
+            location: pos.map(|x| Box::new(SourceLocation::new(locate_at(x, x)))),
+            expected: expected.into_iter().map(String::from).collect(),
+            rule: rule.map(String::from),
+        }
+    })?;
+    Ok(pp_text)
+}
+
+fn line_starts(s: &str) -> Vec<usize> {
+    let mut ret = vec![0];
+    ret.extend(s.match_indices('\n').map(|(i, _)| i + 1));
+    ret
+}
+
+// Get the file and line which the physical line `line` is renamed to by `` `line `` directives
+fn remap(line_map: &[LineDirective], line: usize) -> Option<(&Path, usize)> {
+    let i = line_map.partition_point(|x| x.line < line);
+    let x = line_map.get(i.checked_sub(1)?)?;
+    Some((&x.path, x.number + line - x.line - 1))
+}
+
+pub(crate) fn identifier(node: RefNode, s: &str) -> Option<String> {
     for x in node {
         match x {
             RefNode::SimpleIdentifier(x) => {
@@ -615,7 +1633,104 @@ fn identifier(node: RefNode, s: &str) -> Option<String> {
     None
 }
 
-fn get_str(node: RefNode, s: &str) -> String {
+fn use_macro(x: &TextMacroUsage, s: &str, ctx: &Context) {
+    let (_, ref name, _) = x.nodes;
+    let id = identifier((&name.nodes.0).into(), s).unwrap();
+    ctx.macros.borrow_mut().used.insert(id);
+}
+
+// Record the macros in the condition as used and tested
+fn test_macros<F: Fn(usize, usize) -> Location>(
+    x: &IfdefCondition,
+    s: &str,
+    locate_at: F,
+    ctx: &Context,
+) {
+    let mut macros = ctx.macros.borrow_mut();
+    for node in x {
+        if let RefNode::TextMacroIdentifier(x) = node {
+            let id = identifier(x.into(), s).unwrap();
+            let locate: Locate = x.try_into().unwrap();
+            let site = locate_at(locate.offset, locate.offset + locate.len);
+            macros.used.insert(id.clone());
+            macros.tested.push((id, site));
+        }
+    }
+}
+
+pub(crate) fn ifdef_condition(x: &IfdefCondition, s: &str, defines: &Defines) -> bool {
+    match x {
+        IfdefCondition::TextMacroIdentifier(x) => {
+            let id = identifier((&**x).into(), s).unwrap();
+            defines.contains_key(&id)
+        }
+        IfdefCondition::IfdefMacroExpression(x) => ifdef_macro_expression(x, s, defines),
+    }
+}
+
+// Binary operators are parsed without precedence, so the operands and the operators are
+// flattened and combined in the order of `&&`, `||`, then `->` and `<->` (right associative).
+fn ifdef_macro_expression(x: &IfdefMacroExpression, s: &str, defines: &Defines) -> bool {
+    let mut operands = Vec::new();
+    let mut operators = Vec::new();
+    flatten_ifdef_macro_expression(x, s, defines, &mut operands, &mut operators);
+
+    for level in &[&["&&"][..], &["||"][..]] {
+        let mut i = 0;
+        while i < operators.len() {
+            if level.contains(&operators[i]) {
+                let rhs = operands.remove(i + 1);
+                operands[i] = match operators.remove(i) {
+                    "&&" => operands[i] && rhs,
+                    _ => operands[i] || rhs,
+                };
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    let mut ret = operands.pop().unwrap();
+    while let Some(operator) = operators.pop() {
+        let lhs = operands.pop().unwrap();
+        ret = match operator {
+            "->" => !lhs || ret,
+            _ => lhs == ret,
+        };
+    }
+    ret
+}
+
+fn flatten_ifdef_macro_expression<'a>(
+    x: &IfdefMacroExpression,
+    s: &'a str,
+    defines: &Defines,
+    operands: &mut Vec<bool>,
+    operators: &mut Vec<&'a str>,
+) {
+    match x {
+        IfdefMacroExpression::TextMacroIdentifier(x) => {
+            let id = identifier((&**x).into(), s).unwrap();
+            operands.push(defines.contains_key(&id));
+        }
+        IfdefMacroExpression::Paren(x) => {
+            let (_, ref x, _) = x.nodes.0.nodes;
+            operands.push(ifdef_macro_expression(x, s, defines));
+        }
+        IfdefMacroExpression::Not(x) => {
+            let (_, ref x) = x.nodes;
+            operands.push(!ifdef_macro_expression(x, s, defines));
+        }
+        IfdefMacroExpression::Binary(x) => {
+            let (ref lhs, ref operator, ref rhs) = x.nodes;
+            flatten_ifdef_macro_expression(lhs, s, defines, operands, operators);
+            operators.push(operator.nodes.0.str(s));
+            flatten_ifdef_macro_expression(rhs, s, defines, operands, operators);
+        }
+    }
+}
+
+pub(crate) fn get_str(node: RefNode, s: &str) -> String {
     let mut ret = String::from("");
     for x in node {
         match x {
@@ -628,57 +1743,124 @@ fn get_str(node: RefNode, s: &str) -> String {
     ret
 }
 
-fn split_text(s: &str) -> Vec<String> {
-    let mut is_string = false;
-    let mut is_ident = false;
-    let mut is_backquote_prev = false;
-    let mut is_comment = false;
-    let mut is_ident_prev;
-    let mut x = String::from("");
-    let mut ret = vec![];
+// Token of a text macro body (IEEE 1800-2017 22.5.1)
+#[derive(Clone, Debug, PartialEq)]
+enum MacroToken {
+    // Text copied to the expansion as is
+    Text(String),
+    // Identifier replaced by the actual argument if it is a formal argument
+    Identifier(String),
+    // `"
+    Quote,
+    // `\`"
+    EscapedQuote,
+    // ``
+    Paste,
+}
 
-    let mut iter = s.chars().peekable();
-    while let Some(c) = iter.next() {
-        is_ident_prev = is_ident;
-        is_ident = c.is_ascii_alphanumeric() | (c == '_');
+// Get the tokens with their byte offsets in `s`
+fn macro_tokens(s: &str) -> Vec<(usize, MacroToken)> {
+    let chars: Vec<char> = s.chars().collect();
+    let offsets: Vec<usize> = s.char_indices().map(|(i, _)| i).collect();
+    let starts_with = |i: usize, x: &str| {
+        x.chars()
+            .enumerate()
+            .all(|(j, c)| chars.get(i + j) == Some(&c))
+    };
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '$';
+    let take_while = |mut i: usize, f: &dyn Fn(char) -> bool| {
+        while i < chars.len() && f(chars[i]) {
+            i += 1;
+        }
+        i
+    };
 
-        if c == '\n' && is_comment {
-            is_comment = false;
-            x.push(c);
-        } else if is_comment {
-            continue;
-        } else if c == '"' && is_backquote_prev {
-            x.push(c);
-            ret.push(x);
-            x = String::from("");
-        } else if c == '"' && !is_string {
-            ret.push(x);
-            x = String::from("");
-            x.push(c);
-            is_string = true;
-        } else if c == '"' && is_string {
-            x.push(c);
-            ret.push(x);
-            x = String::from("");
-            is_string = false;
-        } else if c == '/' && iter.peek() == Some(&'/') && !is_string {
-            is_comment = true;
-        } else if !is_string {
-            if is_ident != is_ident_prev {
-                ret.push(x);
-                x = String::from("");
+    // between `" and `"
+    let mut stringify = false;
+    let mut ret = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let (token, next) = if starts_with(i, "`\\`\"") {
+            (MacroToken::EscapedQuote, i + 4)
+        } else if starts_with(i, "`\"") {
+            stringify = !stringify;
+            (MacroToken::Quote, i + 2)
+        } else if starts_with(i, "``") {
+            (MacroToken::Paste, i + 2)
+        } else if starts_with(i, "\\\r\n") {
+            (MacroToken::Text(String::from("\r\n")), i + 3)
+        } else if starts_with(i, "\\\n") || starts_with(i, "\\\r") {
+            (MacroToken::Text(chars[i + 1].to_string()), i + 2)
+        } else if c == '"' && !stringify {
+            // string literal without substitution
+            let mut x = String::from("\"");
+            let mut j = i + 1;
+            while j < chars.len() && chars[j] != '"' {
+                if starts_with(j, "\\\r\n") {
+                    x.push_str("\r\n");
+                    j += 3;
+                } else if starts_with(j, "\\\n") || starts_with(j, "\\\r") {
+                    x.push(chars[j + 1]);
+                    j += 2;
+                } else if chars[j] == '\\' && j + 1 < chars.len() {
+                    x.push(chars[j]);
+                    x.push(chars[j + 1]);
+                    j += 2;
+                } else {
+                    x.push(chars[j]);
+                    j += 1;
+                }
+            }
+            if j < chars.len() {
+                x.push('"');
+                j += 1;
             }
-            x.push(c);
+            (MacroToken::Text(x), j)
+        } else if starts_with(i, "//") && !stringify {
+            // one-line comment isn't a part of the expansion
+            let next = take_while(i, &|c| c != '\n');
+            (MacroToken::Text(String::new()), next)
+        } else if starts_with(i, "/*") && !stringify {
+            let mut next = i + 2;
+            while next < chars.len() && !starts_with(next, "*/") {
+                next += 1;
+            }
+            let next = (next + 2).min(chars.len());
+            (MacroToken::Text(chars[i..next].iter().collect()), next)
+        } else if c == '\\' {
+            // escaped identifier
+            let next = take_while(i + 1, &|c| !c.is_whitespace());
+            (MacroToken::Text(chars[i..next].iter().collect()), next)
+        } else if c == '$' || c.is_ascii_digit() {
+            // system task name or number
+            let next = take_while(i + 1, &|c| is_ident(c) || c == '.');
+            (MacroToken::Text(chars[i..next].iter().collect()), next)
+        } else if c == '\'' && matches!(chars.get(i + 1), Some(x) if "sSbBoOdDhH".contains(*x)) {
+            // based number
+            let next = if "sS".contains(chars[i + 1]) {
+                i + 3
+            } else {
+                i + 2
+            };
+            let next = take_while(next.min(chars.len()), &|c| is_ident(c) || c == '?');
+            (MacroToken::Text(chars[i..next].iter().collect()), next)
+        } else if is_ident(c) {
+            let next = take_while(i, &is_ident);
+            (
+                MacroToken::Identifier(chars[i..next].iter().collect()),
+                next,
+            )
         } else {
-            x.push(c);
-        }
-
-        is_backquote_prev = c == '`';
+            (MacroToken::Text(c.to_string()), i + 1)
+        };
+        ret.push((offsets[i], token));
+        i = next;
     }
-    ret.push(x);
     ret
 }
 
+#[allow(clippy::too_many_arguments)]
 fn resolve_text_macro_usage<T: AsRef<Path>, U: AsRef<Path>>(
     x: &TextMacroUsage,
     s: &str,
@@ -687,12 +1869,16 @@ fn resolve_text_macro_usage<T: AsRef<Path>, U: AsRef<Path>>(
     include_paths: &[U],
     strip_comments: bool,
     resolve_depth: usize,
-) -> Result<Option<(String, Option<(PathBuf, Range)>, Defines)>, Error> {
+    site: &Location,
+    map: Option<&TextMap>,
+    ctx: &Context,
+) -> Result<Option<(PreprocessedText, Defines)>, Error> {
     let (_, ref name, ref args) = x.nodes;
     let id = identifier((&name.nodes.0).into(), &s).unwrap();
+    let location = || Some(Box::new(SourceLocation::new(site.clone())));
 
     if resolve_depth > RECURSIVE_LIMIT {
-        return Err(Error::ExceedRecursiveLimit);
+        return Err(Error::ExceedRecursiveLimit(location()));
     }
 
     let mut args_str = String::from("");
@@ -708,8 +1894,7 @@ fn resolve_text_macro_usage<T: AsRef<Path>, U: AsRef<Path>>(
         for arg in args.contents() {
             if let Some(arg) = arg {
                 let (ref arg,) = arg.nodes;
-                let arg = arg.str(&s).trim_end();
-                actual_args.push(Some(arg));
+                actual_args.push(Some((arg.str(&s).trim_end(), arg.offset)));
             } else {
                 actual_args.push(None);
             }
@@ -719,30 +1904,36 @@ fn resolve_text_macro_usage<T: AsRef<Path>, U: AsRef<Path>>(
     let define = defines.get(&id);
     if let Some(Some(define)) = define {
         let mut arg_map = HashMap::new();
+        let mut arguments = Vec::new();
 
         if !define.arguments.is_empty() && no_args {
-            return Err(Error::DefineNoArgs);
+            return Err(Error::DefineNoArgs(location()));
         }
 
         for (i, (arg, default)) in define.arguments.iter().enumerate() {
-            let value = match actual_args.get(i) {
-                Some(Some(actual_arg)) => *actual_arg,
+            let (value, offset, default) = match actual_args.get(i) {
+                Some(Some((actual_arg, offset))) => (*actual_arg, Some(*offset), false),
                 Some(None) => {
                     if let Some(default) = default {
-                        default
+                        (default.as_str(), None, true)
                     } else {
-                        ""
+                        ("", None, false)
                     }
                 }
                 None => {
                     if let Some(default) = default {
-                        default
+                        (default.as_str(), None, true)
                     } else {
-                        return Err(Error::DefineArgNotFound(String::from(arg)));
+                        return Err(Error::DefineArgNotFound(String::from(arg), location()));
                     }
                 }
             };
-            arg_map.insert(String::from(arg), value);
+            arg_map.insert(String::from(arg), (value, offset));
+            arguments.push(MacroArgument {
+                name: String::from(arg),
+                value: String::from(value),
+                default,
+            });
         }
 
         // restore () for textmacro without arguments
@@ -753,32 +1944,52 @@ fn resolve_text_macro_usage<T: AsRef<Path>, U: AsRef<Path>>(
         };
 
         if let Some(ref text) = define.text {
+            // The origin of each token is recorded in the substitution.
+            // Tokens of the macro text come from the definition,
+            // and actual arguments come from the usage.
             let mut replaced = String::from("");
-            for text in split_text(&text.text) {
-                if let Some(value) = arg_map.get(&text) {
-                    replaced.push_str(*value);
-                } else {
-                    replaced.push_str(
-                        &text
-                            .replace("``", "")
-                            .replace("`\\`\"", "\\\"")
-                            .replace("`\"", "\"")
-                            .replace("\\\n", "\n")
-                            .replace("\\\r\n", "\r\n")
-                            .replace("\\\r", "\r"),
-                    );
+            let mut replaced_map = TextMap::default();
+            for (pos, token) in macro_tokens(&text.text) {
+                let begin = replaced.len();
+                let mut origin = text
+                    .origin
+                    .as_ref()
+                    .map(|(x, y)| (x.as_path(), y.begin + pos));
+                match token {
+                    MacroToken::Text(x) => replaced.push_str(&x),
+                    MacroToken::Identifier(x) => match arg_map.get(&x) {
+                        Some((value, offset)) => {
+                            replaced.push_str(value);
+                            origin = None;
+                            if let Some(offset) = offset {
+                                let (end, path) = (offset + value.len(), path.as_ref());
+                                replaced_map.push_from(begin, *offset, end, path, map);
+                                continue;
+                            }
+                        }
+                        None => replaced.push_str(&x),
+                    },
+                    MacroToken::Quote => replaced.push('"'),
+                    MacroToken::EscapedQuote => replaced.push_str("\\\""),
+                    MacroToken::Paste => (),
+                }
+                if replaced.len() > begin {
+                    replaced_map.push(begin, origin);
                 }
             }
 
-            if let Some(paren) = paren {
+            if let Some(paren) = paren.filter(|x| !x.is_empty()) {
+                replaced_map.push(replaced.len(), None);
                 replaced.push_str(&paren);
             }
 
-            // separator is required
+            // separator is required, which continues from the last token
             replaced.push_str(" ");
             // remove leading whitespace
+            let trimmed = replaced.len() - replaced.trim_start().len();
             replaced = String::from(replaced.trim_start());
-            let (replaced, new_defines) = preprocess_str(
+            replaced_map.trim_start(trimmed);
+            let (mut replaced, new_defines) = preprocess_str_impl(
                 &replaced,
                 path.as_ref(),
                 &defines,
@@ -786,25 +1997,56 @@ fn resolve_text_macro_usage<T: AsRef<Path>, U: AsRef<Path>>(
                 false,
                 strip_comments,
                 resolve_depth,
-            )?;
-            Ok(Some((
-                String::from(replaced.text()),
-                text.origin.clone(),
-                new_defines,
-            )))
+                Some(site),
+                Some(&replaced_map),
+                ctx,
+            )
+            .map_err(|x| x.expanded(Expansion::Macro(id.clone())))?;
+
+            let locate: Locate = x.try_into().unwrap();
+            let usage = locate.str(s).trim_end();
+            let site = text_range(
+                path.as_ref(),
+                locate.offset,
+                locate.offset + usage.len(),
+                map,
+            );
+            replaced.push_frame(OriginFrame::Macro {
+                name: id.clone(),
+                site: site.clone(),
+                define: text
+                    .origin
+                    .as_ref()
+                    .map(|(x, y)| (x.clone(), (y.begin, y.end))),
+            });
+            let define = ctx.expanded_define(&id, define);
+            let expansion = MacroExpansion {
+                name: id,
+                usage: String::from(usage),
+                site,
+                define,
+                arguments,
+                range: (0, replaced.text.len()),
+                children: replaced.expansions.drain(..).collect(),
+            };
+            replaced.expansions.push(expansion);
+            Ok(Some((replaced, new_defines)))
         } else {
             Ok(None)
         }
     } else if define.is_some() {
         Ok(None)
     } else {
-        Err(Error::DefineNotFound(id))
+        Err(Error::DefineNotFound(id, location()))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cache::IncludeCache;
+    use crate::encoding::Encoding;
+    use crate::provider::{DecodingFileSystem, MemoryFileProvider, RelativeIncludeResolver};
     use std::env;
 
     fn get_testcase(s: &str) -> String {
@@ -1046,7 +2288,10 @@ endmodule
             false,
             false,
         );
-        assert_eq!(format!("{:?}", ret), "Err(ExceedRecursiveLimit)");
+        let err = ret.unwrap_err();
+        assert!(matches!(err, Error::ExceedRecursiveLimit(_)));
+        let location = err.location().unwrap();
+        assert_eq!((location.location.line, location.location.column), (3, 1));
     }
 
     #[test]
@@ -1058,7 +2303,7 @@ endmodule
             false,
             false,
         );
-        assert_eq!(format!("{:?}", ret), "Err(ExceedRecursiveLimit)");
+        assert!(matches!(ret, Err(Error::ExceedRecursiveLimit(_))));
     }
 
     #[test]
@@ -1071,7 +2316,10 @@ endmodule
             false,
             false,
         );
-        assert_eq!(format!("{:?}", ret), "Err(IncludeLine)");
+        let err = ret.unwrap_err();
+        assert!(matches!(err, Error::IncludeLine(_)));
+        let location = err.location().unwrap();
+        assert_eq!((location.location.line, location.location.column), (2, 24));
     }
 
     #[test]
@@ -1084,7 +2332,7 @@ endmodule
             false,
             false,
         );
-        assert_eq!(format!("{:?}", ret), "Err(IncludeLine)");
+        assert!(matches!(ret, Err(Error::IncludeLine(_))));
     }
 
     #[test]
@@ -1339,4 +2587,864 @@ endmodule
         );
         assert_eq!(ret.origin(80).unwrap().1, 60);
     }
+
+    #[test]
+    fn test21() {
+        let include_paths = [get_testcase("")];
+        let ret = preprocess(
+            get_testcase("test21.sv"),
+            &HashMap::new(),
+            &include_paths,
+            false,
+            false,
+        );
+        let err = ret.unwrap_err();
+        let site = Location {
+            path: PathBuf::from(get_testcase("test21.sv")),
+            begin: 25,
+            end: 47,
+            line: 2,
+            column: 1,
+        };
+        assert_eq!(err.location(), Some(&SourceLocation::new(site.clone())));
+        if let Error::Include { source, .. } = err {
+            assert!(matches!(*source, Error::DefineNotFound(ref x, _) if x == "y"));
+            let location = source.location().unwrap();
+            assert_eq!(
+                location.location.path,
+                PathBuf::from(get_testcase("test21.svh"))
+            );
+            assert_eq!((location.location.line, location.location.column), (2, 10));
+            assert_eq!(
+                location.expansions,
+                vec![
+                    Expansion::Macro(String::from("x")),
+                    Expansion::Include(site)
+                ]
+            );
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[test]
+    fn test22() {
+        let include_paths = [get_testcase("")];
+        let (ret, _) = preprocess(
+            get_testcase("test22.sv"),
+            &HashMap::new(),
+            &include_paths,
+            false,
+            false,
+        )
+        .unwrap();
+
+        let pos = ret.text().find("x;").unwrap();
+        let range = ret.source_range(pos, pos + 1, 4).unwrap();
+        assert_eq!(range.path, PathBuf::from(get_testcase("test22.sv")));
+        assert_eq!(
+            range.begin,
+            Position {
+                offset: 31,
+                line: 2,
+                column: 7,
+                char_column: 13
+            }
+        );
+        assert_eq!((range.end.column, range.end.char_column), (8, 14));
+        let range = ret.source_range(pos, pos + 1, 8).unwrap();
+        assert_eq!(range.begin.char_column, 17);
+
+        let pos = ret.text().find("input").unwrap();
+        let range = ret.source_range(pos, pos + 5, 4).unwrap();
+        assert_eq!(range.path, PathBuf::from(get_testcase("test2.svh")));
+        assert_eq!((range.begin.line, range.begin.column), (2, 1));
+        assert_eq!((range.end.line, range.end.column), (2, 6));
+    }
+
+    #[test]
+    fn test23() {
+        let include_paths = [get_testcase("")];
+        let (ret, _) = preprocess(
+            get_testcase("test23.sv"),
+            &HashMap::new(),
+            &include_paths,
+            false,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            ret.text(),
+            r##"`define add(a, b) a + b
+`define mul(a, b) a * `add(a, b)
+wire x = 1 * 1 + 2 ;
+wire y = 3;
+
+"##
+        );
+        let sv = PathBuf::from(get_testcase("test23.sv"));
+        let svh = PathBuf::from(get_testcase("test23.svh"));
+        let include = OriginFrame::Include {
+            site: Some((sv.clone(), (24, 46))),
+        };
+        let mul = OriginFrame::Macro {
+            name: String::from("mul"),
+            site: Some((svh.clone(), (42, 52))),
+            define: Some((svh.clone(), (17, 32))),
+        };
+        let add = OriginFrame::Macro {
+            name: String::from("add"),
+            site: Some((svh.clone(), (22, 32))),
+            define: Some((sv.clone(), (17, 23))),
+        };
+
+        // `*` comes from the body of `mul`
+        let base = ret.text().find("1 * 1 + 2").unwrap();
+        let pos = base + 2;
+        assert_eq!(ret.origin(pos), Some((&svh, 20)));
+        let frames = ret.origin_frames(pos).unwrap();
+        assert_eq!(frames, &[mul.clone(), include.clone()]);
+
+        // `+` comes from the body of `add` called in the body of `mul`
+        let pos = base + 6;
+        assert_eq!(ret.origin(pos), Some((&sv, 20)));
+        let frames = ret.origin_frames(pos).unwrap();
+        assert_eq!(frames, &[add, mul, include]);
+
+        let location = ret.source_location(pos).unwrap();
+        assert_eq!((location.location.line, location.location.column), (1, 21));
+        assert_eq!(location.expansions.len(), 3);
+        assert_eq!(
+            location.expansions[0],
+            Expansion::Macro(String::from("add"))
+        );
+        assert_eq!(
+            location.expansions[1],
+            Expansion::Macro(String::from("mul"))
+        );
+        if let Expansion::Include(x) = &location.expansions[2] {
+            assert_eq!((x.line, x.column), (2, 1));
+        } else {
+            unreachable!();
+        }
+
+        // `__LINE__` is replaced text at the position directive
+        let pos = ret.text().find("3;").unwrap();
+        assert_eq!(ret.origin(pos), Some((&svh, 64)));
+        assert_eq!(ret.origin_frames(pos).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test24() {
+        let (ret, _) = preprocess(
+            get_testcase("test24.sv"),
+            &HashMap::new(),
+            &[] as &[String],
+            false,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            ret.text(),
+            r##"`define A
+`define C
+wire a;
+
+wire d;
+
+wire e;
+
+wire f;
+
+wire h;
+
+-> ev;
+
+"##
+        );
+    }
+
+    #[test]
+    fn test25() {
+        let mut files = MemoryFileProvider::new();
+        files.insert(
+            "src/top.sv",
+            String::from("module A;\n`include \"a.svh\"\nendmodule\n"),
+        );
+        files.insert("src/a.svh", String::from("`include \"b.svh\"\n"));
+        files.insert("inc/b.svh", String::from("wire b;\n"));
+
+        let (ret, _) = preprocess_with_provider(
+            "src/top.sv",
+            &HashMap::new(),
+            &["inc"],
+            false,
+            false,
+            &files,
+            &RelativeIncludeResolver::default(),
+        )
+        .unwrap();
+        assert_eq!(ret.text(), "module A;\nwire b;\n\n\nendmodule\n");
+        assert_eq!(
+            ret.source("inc/b.svh", &files),
+            Some(String::from("wire b;\n"))
+        );
+        assert_eq!(ret.source("inc/c.svh", &files), None);
+
+        // the default resolver doesn't search the directory of the including file
+        let ret = preprocess_with_provider(
+            "src/top.sv",
+            &HashMap::new(),
+            &["inc"],
+            false,
+            false,
+            &files,
+            &DefaultIncludeResolver,
+        );
+        assert!(matches!(ret, Err(Error::Include { .. })));
+    }
+
+    #[test]
+    fn test26() {
+        let include_paths = [get_testcase("")];
+        let (ret, _) = preprocess(
+            get_testcase("test26.sv"),
+            &HashMap::new(),
+            &include_paths,
+            false,
+            false,
+        )
+        .unwrap();
+        let dependencies: Vec<_> = ret
+            .dependencies()
+            .iter()
+            .map(|x| {
+                let path = x.path.file_name().unwrap().to_string_lossy();
+                let site = x.site.path.file_name().unwrap().to_string_lossy();
+                (path.into_owned(), site.into_owned(), x.site.line)
+            })
+            .collect();
+        assert_eq!(
+            dependencies,
+            vec![
+                (String::from("test2.svh"), String::from("test26.sv"), 2),
+                (String::from("test26.svh"), String::from("test26.sv"), 3),
+                (String::from("test2.svh"), String::from("test26.svh"), 1),
+            ]
+        );
+        assert_eq!(
+            ret.dependencies()[0].path,
+            PathBuf::from(get_testcase("test2.svh"))
+        );
+    }
+
+    #[test]
+    fn test27() {
+        let include_paths = [get_testcase("")];
+        let (ret, _) = preprocess(
+            get_testcase("test27.sv"),
+            &HashMap::new(),
+            &include_paths,
+            false,
+            false,
+        )
+        .unwrap();
+        let warnings: Vec<_> = ret
+            .warnings()
+            .iter()
+            .map(|x| {
+                let site = x.site().path.file_name().unwrap().to_string_lossy();
+                let kind = match x {
+                    Warning::Redefined { previous, .. } => {
+                        format!("redefined:{}", previous.as_ref().unwrap().line)
+                    }
+                    Warning::UndefNotDefined { .. } => String::from("undef"),
+                    Warning::Unused { .. } => String::from("unused"),
+                    Warning::NeverDefined { .. } => String::from("never"),
+                };
+                format!("{} {} {}:{}", kind, x.name(), site, x.site().line)
+            })
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![
+                "redefined:1 A test27.sv:2",
+                "undef C test27.sv:5",
+                "unused B test27.sv:3",
+                "unused B test27.sv:4",
+                "unused UNUSED test27.svh:1",
+                "never D test27.sv:7",
+            ]
+        );
+    }
+
+    #[test]
+    fn test28() {
+        let include_paths = [get_testcase("")];
+        let (ret, _) = preprocess(
+            get_testcase("test28.sv"),
+            &HashMap::new(),
+            &include_paths,
+            false,
+            false,
+        )
+        .unwrap();
+        let text = ret
+            .text_with_line_directives()
+            .replace(&get_testcase(""), "");
+        assert_eq!(
+            text,
+            r##"`line 1 "test28.sv" 0
+module A;
+`line 1 "test28.svh" 1
+`define W \
+  1
+wire a;
+
+
+`line 6 "test28.sv" 2
+wire b = 1 ;
+
+`line 10 "test28.sv" 0
+endmodule
+"##
+        );
+    }
+
+    #[test]
+    fn test29() {
+        let src = "module A;\n`line 100 \"template.sv\" 0\nwire a;\n  wire b = c;\nendmodule\n";
+        let (ret, _) =
+            preprocess_str(src, "test29.sv", &HashMap::new(), &[""], false, false, 0).unwrap();
+        let pos = ret.text().find("wire a").unwrap();
+        let location = ret.location(pos).unwrap();
+        assert_eq!(location.path, PathBuf::from("template.sv"));
+        assert_eq!((location.line, location.column), (100, 1));
+        assert_eq!(ret.origin(pos), Some((&PathBuf::from("test29.sv"), 36)));
+        assert_eq!(ret.location(0).unwrap().path, PathBuf::from("test29.sv"));
+
+        let pos = ret.text().find("c;").unwrap();
+        let range = ret.source_range(pos, pos + 1, 8).unwrap();
+        assert_eq!(range.path, PathBuf::from("template.sv"));
+        assert_eq!(
+            (range.begin.line, range.begin.column, range.begin.offset),
+            (101, 12, 55)
+        );
+
+        let src = src.replace("c;", "`c;");
+        let err =
+            preprocess_str(&src, "test29.sv", &HashMap::new(), &[""], false, false, 0).unwrap_err();
+        let location = &err.location().unwrap().location;
+        assert_eq!(location.path, PathBuf::from("template.sv"));
+        assert_eq!((location.line, location.column), (101, 12));
+    }
+
+    #[test]
+    fn test30() {
+        let src = "module A;\n`ifdef X\nwire a;\n`elsif Y\nwire b;\n`else\n  wire c;\n`endif\n`ifndef Z\nwire d;\n`endif\nendmodule\n";
+        let mut defines = HashMap::new();
+        defines.insert(String::from("Y"), None);
+        let (ret, _) = preprocess_str(src, "test30.sv", &defines, &[""], false, false, 0).unwrap();
+        let regions: Vec<_> = ret
+            .inactive_regions()
+            .iter()
+            .map(|x| {
+                let text = &src[x.location.begin..x.location.end];
+                (
+                    x.directive.as_str(),
+                    x.condition.as_str(),
+                    x.location.line,
+                    text,
+                )
+            })
+            .collect();
+        assert_eq!(
+            regions,
+            vec![("ifdef", "X", 3, "wire a;\n"), ("else", "", 7, "wire c;\n"),]
+        );
+    }
+
+    #[test]
+    fn test31() {
+        let src = "`define INC(x, y = 1) ((x) + (y))\n`define TWICE(a) `INC(a, a)\nmodule A;\nwire b = `TWICE(c);\nwire d = `INC(e);\nendmodule\n";
+        let (ret, _) =
+            preprocess_str(src, "test31.sv", &HashMap::new(), &[""], false, false, 0).unwrap();
+        let expansions = ret.expansions();
+        assert_eq!(expansions.len(), 2);
+
+        let twice = &expansions[0];
+        assert_eq!(twice.name, "TWICE");
+        assert_eq!(twice.usage, "`TWICE(c)");
+        let (site_path, site_range) = twice.site.clone().unwrap();
+        assert_eq!(site_path, PathBuf::from("test31.sv"));
+        assert_eq!(&src[site_range.0..site_range.1], "`TWICE(c)");
+        assert_eq!(twice.define.identifier, "TWICE");
+        assert_eq!(twice.arguments[0].value, "c");
+        assert_eq!(&ret.text()[twice.range.0..twice.range.1], "((c) + (c)) ");
+
+        let inc = &twice.children[0];
+        assert_eq!(inc.usage, "`INC(c, c)");
+        let (_, site_range) = inc.site.clone().unwrap();
+        assert_eq!(&src[site_range.0..site_range.1], "`INC(a, a)");
+        assert_eq!(&ret.text()[inc.range.0..inc.range.1], "((c) + (c)) ");
+
+        let inc = &expansions[1];
+        let arguments: Vec<_> = inc
+            .arguments
+            .iter()
+            .map(|x| (x.name.as_str(), x.value.as_str(), x.default))
+            .collect();
+        assert_eq!(arguments, vec![("x", "e", false), ("y", "1", true)]);
+        assert!(inc.children.is_empty());
+        assert_eq!(&ret.text()[inc.range.0..inc.range.1], "((e) + (1)) ");
+        assert!(Arc::ptr_eq(&inc.define, &twice.children[0].define));
+    }
+
+    // Examples of IEEE 1800-2017 22.5.1.
+    // `x.sv` is expected to be preprocessed to `x.out` or fail with the error in `x.err`.
+    #[test]
+    fn test_lrm_text_macro() {
+        let dir = get_testcase("lrm");
+        let mut paths: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|x| x.unwrap().path())
+            .filter(|x| x.extension() == Some("sv".as_ref()))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+        for path in paths {
+            let ret = preprocess(&path, &HashMap::new(), &[""], false, false);
+            let out = std::fs::read_to_string(path.with_extension("out"));
+            let err = std::fs::read_to_string(path.with_extension("err"));
+            match (ret, out, err) {
+                (Ok((ret, _)), Ok(out), _) => assert_eq!(ret.text(), out, "{:?}", path),
+                (Err(ret), _, Ok(err)) => {
+                    let ret = format!("{}", ret).replace(&dir, "");
+                    assert_eq!(ret, err.trim_end(), "{:?}", path)
+                }
+                (ret, _, _) => panic!("{:?}: unexpected result {:?}", path, ret.map(|x| x.0.text)),
+            }
+        }
+    }
+
+    #[test]
+    fn test32() {
+        let define = Define::parse("WIDTH=8").unwrap();
+        assert_eq!(define.identifier, "WIDTH");
+        assert!(define.arguments.is_empty());
+        assert_eq!(define.text, Some(DefineText::new(String::from("8"), None)));
+
+        let define = Define::parse("MAX(a,b=0)=((a)>(b)?(a):(b))").unwrap();
+        assert_eq!(define.identifier, "MAX");
+        assert_eq!(
+            define.arguments,
+            vec![
+                (String::from("a"), None),
+                (String::from("b"), Some(String::from("0")))
+            ]
+        );
+        assert_eq!(define.text.unwrap().text, "((a)>(b)?(a):(b))");
+
+        let define = Define::parse("DEBUG").unwrap();
+        assert_eq!(define.identifier, "DEBUG");
+        assert_eq!(define.text, None);
+
+        let define = Define::parse("URL=\"http://x\"").unwrap();
+        assert_eq!(define.text.unwrap().text, "\"http://x\"");
+
+        for x in &[
+            "8X=1",
+            "=1",
+            "",
+            "FOO BAR",
+            "FOO =1",
+            "MAX(a",
+            "URL=http://x",
+        ] {
+            match Define::parse(x) {
+                Err(Error::DefineArg(y)) => assert_eq!(&y, x),
+                y => panic!("{:?}", y),
+            }
+        }
+
+        let mut defines = load_defines(get_testcase("test32.svh")).unwrap();
+        defines.insert(
+            String::from("MAX"),
+            Some(Define::parse("MAX(a,b)=((a)>(b)?(a):(b))").unwrap()),
+        );
+        let (ret, _) = preprocess_str(
+            "wire [`WIDTH-1:0] a = `MAX(1, 2);\n",
+            "test32.sv",
+            &defines,
+            &[""],
+            false,
+            false,
+            0,
+        )
+        .unwrap();
+        assert_eq!(ret.text(), "wire [8 -1:0] a = ((1)>(2)?(1):(2)) ;\n");
+        assert!(defines.contains_key("DEBUG"));
+        assert!(!defines.contains_key("UNUSED"));
+    }
+
+    #[test]
+    fn test33() {
+        let path = get_testcase("test33.sv");
+        let files = DecodingFileSystem::new(Encoding::Utf8);
+        let ret = preprocess_with_provider(
+            &path,
+            &HashMap::new(),
+            &[""],
+            false,
+            false,
+            &files,
+            &DefaultIncludeResolver,
+        );
+        assert!(matches!(ret, Err(Error::File { .. })));
+
+        // non-UTF-8 files are read as Latin-1 by default
+        let (ret, _) = preprocess(&path, &HashMap::new(), &[""], false, false).unwrap();
+        assert!(ret.text().contains("caf\u{e9}"));
+        let pos = ret.text().find("wire").unwrap();
+        assert_eq!(ret.origin(pos), Some((&PathBuf::from(&path), 18)));
+        let location = ret.location(pos).unwrap();
+        assert_eq!((location.begin, location.line, location.column), (18, 2, 1));
+
+        let files = DecodingFileSystem::new(Encoding::Utf8Lossy);
+        let (ret, _) = preprocess_with_provider(
+            &path,
+            &HashMap::new(),
+            &[""],
+            false,
+            false,
+            &files,
+            &DefaultIncludeResolver,
+        )
+        .unwrap();
+        assert!(ret.text().contains("caf\u{fffd}"));
+        let pos = ret.text().find("wire").unwrap();
+        assert_eq!(ret.origin(pos), Some((&PathBuf::from(&path), 18)));
+
+        let include_paths = [get_testcase("")];
+        let (ret, _) = preprocess(
+            get_testcase("test33_bom.sv"),
+            &HashMap::new(),
+            &include_paths,
+            false,
+            false,
+        )
+        .unwrap();
+        assert_eq!(ret.text(), "module A;\r\nwire a;\r\n\r\nendmodule\r\n");
+        assert_eq!(ret.origin(0).unwrap().1, 3);
+        let pos = ret.text().find("wire").unwrap();
+        assert_eq!(ret.origin(pos).unwrap().1, 3);
+        let location = ret.location(ret.text().find("endmodule").unwrap()).unwrap();
+        assert_eq!((location.begin, location.line, location.column), (41, 3, 1));
+        let range = ret.source_range(pos, pos + 4, 8).unwrap();
+        assert_eq!((range.begin.offset, range.end.offset), (3, 7));
+    }
+
+    #[test]
+    fn test34() {
+        let s = "module A;\n`define X 1\nwire a = `X;\nendmodule\n";
+        let (ret, _) = preprocess_str(
+            s,
+            "test34.sv",
+            &HashMap::new(),
+            &[] as &[String],
+            false,
+            false,
+            0,
+        )
+        .unwrap();
+        assert_eq!(
+            ret.text(),
+            "module A;\n`define X 1\nwire a = 1 ;\nendmodule\n"
+        );
+        // the text before and after the macro usage and the expanded text
+        assert_eq!(ret.map.segments.len(), 3);
+        assert_eq!(ret.map.paths, [PathBuf::from("test34.sv")]);
+
+        let path = PathBuf::from("test34.sv");
+        let pos = ret.text().find("wire").unwrap();
+        assert_eq!(ret.origin(pos), Some((&path, 22)));
+        assert_eq!(ret.origin_frames(pos), Some(&[][..]));
+        let pos = ret.text().find("= 1").unwrap() + 2;
+        assert_eq!(ret.origin(pos), Some((&path, 20)));
+        assert_eq!(ret.origin_frames(pos).unwrap().len(), 1);
+        let pos = ret.text().find("endmodule").unwrap();
+        assert_eq!(ret.origin(pos), Some((&path, 35)));
+        assert_eq!(ret.origin(ret.text().len()), None);
+    }
+
+    // Provider recording the files read
+    struct RecordingProvider {
+        files: MemoryFileProvider,
+        reads: RefCell<Vec<PathBuf>>,
+    }
+
+    impl FileProvider for RecordingProvider {
+        fn read(&self, path: &Path) -> std::io::Result<String> {
+            self.reads.borrow_mut().push(PathBuf::from(path));
+            self.files.read(path)
+        }
+
+        fn exists(&self, path: &Path) -> bool {
+            self.files.exists(path)
+        }
+    }
+
+    #[test]
+    fn test35() {
+        let mut files = MemoryFileProvider::new();
+        files.insert(
+            "top.sv",
+            String::from(
+                "`include \"guarded.svh\"\n`include \"guarded.svh\"\n`include \"other.svh\"\n`include \"other.svh\"\n",
+            ),
+        );
+        files.insert(
+            "guarded.svh",
+            String::from("// header\n`ifndef GUARDED_SVH\n`define GUARDED_SVH\nwire a;\n`endif\n"),
+        );
+        files.insert(
+            "other.svh",
+            String::from("`ifndef OTHER_SVH\n`define OTHER_SVH\nwire b;\n`else\nwire c;\n`endif\n"),
+        );
+        let files = RecordingProvider {
+            files,
+            reads: RefCell::new(Vec::new()),
+        };
+        let cache = IncludeCache::new();
+
+        let (ret, _) = preprocess_with_cache(
+            "top.sv",
+            &HashMap::new(),
+            &[] as &[String],
+            true,
+            false,
+            &files,
+            &DefaultIncludeResolver,
+            &cache,
+        )
+        .unwrap();
+        assert_eq!(ret.text().matches("wire a;").count(), 1);
+        assert_eq!(ret.text().matches("wire b;").count(), 1);
+        assert_eq!(ret.text().matches("wire c;").count(), 1);
+        assert_eq!(ret.dependencies().len(), 4);
+        assert_eq!(
+            cache.guard("guarded.svh"),
+            Some(String::from("GUARDED_SVH"))
+        );
+        assert_eq!(cache.guard("other.svh"), None);
+        assert_eq!(
+            files.reads.take(),
+            ["top.sv", "guarded.svh", "other.svh", "other.svh"]
+                .iter()
+                .map(PathBuf::from)
+                .collect::<Vec<_>>()
+        );
+
+        // the guard is known before the first include in the next compilation
+        let mut defines = HashMap::new();
+        defines.insert(String::from("GUARDED_SVH"), None);
+        let (ret, _) = preprocess_with_cache(
+            "top.sv",
+            &defines,
+            &[] as &[String],
+            true,
+            false,
+            &files,
+            &DefaultIncludeResolver,
+            &cache,
+        )
+        .unwrap();
+        assert!(!ret.text().contains("wire a;"));
+        assert_eq!(
+            files.reads.take(),
+            ["top.sv", "other.svh", "other.svh"]
+                .iter()
+                .map(PathBuf::from)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test36() {
+        let include_paths = [get_testcase("")];
+        let (ret, _) = preprocess(
+            get_testcase("test36.sv"),
+            &HashMap::new(),
+            &include_paths,
+            false,
+            false,
+        )
+        .unwrap();
+        let sv = get_testcase("test36.sv");
+        let svh = get_testcase("test36.svh");
+        let inner = get_testcase("test36_inner.svh");
+        let lines: Vec<_> = ret
+            .text()
+            .lines()
+            .filter(|x| x.contains("$display"))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                format!("initial $display(\"{}\", 4);", sv),
+                // macro text and arguments are at the usage
+                format!("initial $display(\"{}\", 5 );", sv),
+                format!("initial $display(6, \"{}\", 6 );", sv),
+                format!("initial $display(\"{}\", 1);", svh),
+                format!("initial $display(\"{0}\", \"{0}\", 2 );", inner),
+                format!("initial $display(\"{}\", 3 );", svh),
+                String::from("initial $display(\"renamed.sv\", 100);"),
+                String::from("initial $display(\"renamed.sv\", 101 );"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test37() {
+        let src = "module A;\r\n`ifdef X\r\nwire a;\r\n`endif\r\n\twire b;\r\nendmodule\r\n";
+        let (ret, _) =
+            preprocess_str(src, "test37.sv", &HashMap::new(), &[""], false, false, 0).unwrap();
+        let pos = ret.text().find("wire b").unwrap();
+        let location = ret.location(pos).unwrap();
+        assert_eq!(
+            (location.begin, location.line, location.column),
+            (src.find("wire b").unwrap(), 5, 2)
+        );
+
+        let range = ret.source_range(pos, pos + "wire b;".len(), 4).unwrap();
+        assert_eq!((range.end.line, range.end.column), (5, 9));
+        assert_eq!(range.begin.char_column, 5);
+
+        let region = &ret.inactive_regions()[0];
+        assert_eq!(
+            &src[region.location.begin..region.location.end],
+            "wire a;\r\n"
+        );
+        assert_eq!(region.location.line, 3);
+    }
+
+    // Provider recording the files read with the length as a modification stamp
+    struct StampedProvider(RecordingProvider);
+
+    impl FileProvider for StampedProvider {
+        fn read(&self, path: &Path) -> std::io::Result<String> {
+            self.0.read(path)
+        }
+
+        fn exists(&self, path: &Path) -> bool {
+            self.0.exists(path)
+        }
+
+        fn stamp(&self, path: &Path) -> Option<(std::time::SystemTime, u64)> {
+            let len = self.0.files.read(path).ok()?.len();
+            Some((std::time::UNIX_EPOCH, len as u64))
+        }
+    }
+
+    #[test]
+    fn test38() {
+        let mut files = MemoryFileProvider::new();
+        files.insert(
+            "top.sv",
+            String::from("`include \"guarded.svh\"\n`include \"guarded.svh\"\n"),
+        );
+        files.insert(
+            "guarded.svh",
+            String::from("// header\n`ifndef GUARDED_SVH\n`define GUARDED_SVH\nwire a;\n`endif\n"),
+        );
+        let files = StampedProvider(RecordingProvider {
+            files,
+            reads: RefCell::new(Vec::new()),
+        });
+        let cache = IncludeCache::new();
+
+        // the comment outside the guard is output at each include
+        let (ret, _) = preprocess_with_cache(
+            "top.sv",
+            &HashMap::new(),
+            &[] as &[String],
+            false,
+            false,
+            &files,
+            &DefaultIncludeResolver,
+            &cache,
+        )
+        .unwrap();
+        assert_eq!(ret.text().matches("// header").count(), 2);
+        assert_eq!(ret.text().matches("wire a;").count(), 1);
+        assert_eq!(
+            files.0.reads.take(),
+            ["top.sv", "guarded.svh"]
+                .iter()
+                .map(PathBuf::from)
+                .collect::<Vec<_>>()
+        );
+
+        // the files aren't read again while the stamps are unchanged
+        let (ret2, _) = preprocess_with_cache(
+            "top.sv",
+            &HashMap::new(),
+            &[] as &[String],
+            false,
+            false,
+            &files,
+            &DefaultIncludeResolver,
+            &cache,
+        )
+        .unwrap();
+        assert_eq!(ret2.text(), ret.text());
+        assert!(files.0.reads.take().is_empty());
+    }
+
+    #[test]
+    fn test39() {
+        let src = "`define mul(a, b) a * b\nwire x = `mul(100000, 2);\n";
+        let (ret, _) =
+            preprocess_str(src, "test39.sv", &HashMap::new(), &[""], false, false, 0).unwrap();
+        assert_eq!(
+            ret.text(),
+            "`define mul(a, b) a * b\nwire x = 100000 * 2 ;\n"
+        );
+        let origin = |x: &str| {
+            let pos = ret.text().find(x).unwrap();
+            ret.origin(pos).map(|(_, x)| x)
+        };
+
+        // the body comes from the definition, and the arguments come from the usage
+        assert_eq!(origin("100000 "), src.find("100000"));
+        assert_eq!(origin("0 *"), src.find("0,"));
+        assert_eq!(origin("* 2"), Some(20));
+        assert_eq!(origin("2 ;"), src.find("2)"));
+        let pos = ret.text().find("* 2").unwrap();
+        let range = ret.source_range(pos - 7, pos - 1, 1).unwrap();
+        assert_eq!(&src[range.begin.offset..range.end.offset], "100000");
+    }
+
+    #[test]
+    fn test40() {
+        let src = "`define inner(x) x\n`define outer(a) a + `inner(a)\nwire y = `outer(12345);\n";
+        let (ret, _) =
+            preprocess_str(src, "test40.sv", &HashMap::new(), &[""], false, false, 0).unwrap();
+        let outer = &ret.expansions()[0];
+        let (_, site) = outer.site.clone().unwrap();
+        assert_eq!(&src[site.0..site.1], "`outer(12345)");
+
+        // the usage in the macro text is in the definition of `outer`
+        let inner = &outer.children[0];
+        assert_eq!(inner.usage, "`inner(12345)");
+        let (_, site) = inner.site.clone().unwrap();
+        assert_eq!(&src[site.0..site.1], "`inner(a)");
+        let pos = inner.range.0;
+        let frames = ret.origin_frames(pos).unwrap();
+        assert_eq!(frames[0].site(), &inner.site);
+
+        // the argument passed through both expansions comes from the outermost usage
+        let (_, pos) = ret.origin(pos).unwrap();
+        assert_eq!(pos, src.rfind("12345").unwrap());
+    }
 }
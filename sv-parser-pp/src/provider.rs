@@ -0,0 +1,264 @@
+use crate::encoding::{decode, Decoded, Encoding};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Source of the files read by the preprocessor
+pub trait FileProvider {
+    /// Read the whole text of `path`
+    fn read(&self, path: &Path) -> io::Result<String>;
+
+    /// Read the decoded text of `path` with the byte offsets in the file.
+    /// By default, it is the text given by `read` without a byte order mark.
+    fn read_decoded(&self, path: &Path) -> io::Result<Decoded> {
+        self.read(path).map(Decoded::new)
+    }
+
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Get the modification time and the length of `path` to find a change without reading it.
+    /// If `None` is returned, the file is read at every include.
+    fn stamp(&self, _path: &Path) -> Option<(SystemTime, u64)> {
+        None
+    }
+}
+
+fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Files on the local file system.
+/// A file which isn't valid UTF-8 is decoded as ISO-8859-1 like `Encoding::Auto`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FileSystem;
+
+impl FileProvider for FileSystem {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        self.read_decoded(path).map(|x| x.text)
+    }
+
+    fn read_decoded(&self, path: &Path) -> io::Result<Decoded> {
+        decode(&fs::read(path)?, Encoding::Auto)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn stamp(&self, path: &Path) -> Option<(SystemTime, u64)> {
+        file_stamp(path)
+    }
+}
+
+/// Files on the local file system decoded by `encoding`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DecodingFileSystem {
+    pub encoding: Encoding,
+}
+
+impl DecodingFileSystem {
+    pub fn new(encoding: Encoding) -> Self {
+        DecodingFileSystem { encoding }
+    }
+}
+
+impl FileProvider for DecodingFileSystem {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        self.read_decoded(path).map(|x| x.text)
+    }
+
+    fn read_decoded(&self, path: &Path) -> io::Result<Decoded> {
+        decode(&fs::read(path)?, self.encoding)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn stamp(&self, path: &Path) -> Option<(SystemTime, u64)> {
+        file_stamp(path)
+    }
+}
+
+/// Files kept in memory such as unsaved editor buffers.
+/// Paths which aren't registered are read from the fallback provider if any.
+#[derive(Default)]
+pub struct MemoryFileProvider {
+    files: HashMap<PathBuf, String>,
+    fallback: Option<Box<dyn FileProvider>>,
+}
+
+impl MemoryFileProvider {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_fallback<T: FileProvider + 'static>(fallback: T) -> Self {
+        MemoryFileProvider {
+            files: HashMap::new(),
+            fallback: Some(Box::new(fallback)),
+        }
+    }
+
+    pub fn insert<T: AsRef<Path>>(&mut self, path: T, text: String) {
+        self.files.insert(PathBuf::from(path.as_ref()), text);
+    }
+}
+
+impl FileProvider for MemoryFileProvider {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        if let Some(text) = self.files.get(path) {
+            Ok(text.clone())
+        } else if let Some(ref fallback) = self.fallback {
+            fallback.read(path)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not found in memory", path.display()),
+            ))
+        }
+    }
+
+    fn read_decoded(&self, path: &Path) -> io::Result<Decoded> {
+        match (self.files.get(path), &self.fallback) {
+            (None, Some(fallback)) => fallback.read_decoded(path),
+            _ => self.read(path).map(Decoded::new),
+        }
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        if self.files.contains_key(path) {
+            true
+        } else if let Some(ref fallback) = self.fallback {
+            fallback.exists(path)
+        } else {
+            false
+        }
+    }
+
+    fn stamp(&self, path: &Path) -> Option<(SystemTime, u64)> {
+        match (self.files.get(path), &self.fallback) {
+            (None, Some(fallback)) => fallback.stamp(path),
+            _ => None,
+        }
+    }
+}
+
+/// Resolution of the path in `` `include `` directives.
+/// `includer` is the file containing the directive.
+/// If `None` is returned, the path is opened as is.
+pub trait IncludeResolver {
+    fn resolve(
+        &self,
+        path: &Path,
+        includer: &Path,
+        include_paths: &[PathBuf],
+        files: &dyn FileProvider,
+    ) -> Option<PathBuf>;
+}
+
+impl<F> IncludeResolver for F
+where
+    F: Fn(&Path, &Path, &[PathBuf], &dyn FileProvider) -> Option<PathBuf>,
+{
+    fn resolve(
+        &self,
+        path: &Path,
+        includer: &Path,
+        include_paths: &[PathBuf],
+        files: &dyn FileProvider,
+    ) -> Option<PathBuf> {
+        self(path, includer, include_paths, files)
+    }
+}
+
+/// Searches the current directory, then the include paths in order
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultIncludeResolver;
+
+impl IncludeResolver for DefaultIncludeResolver {
+    fn resolve(
+        &self,
+        path: &Path,
+        _includer: &Path,
+        include_paths: &[PathBuf],
+        files: &dyn FileProvider,
+    ) -> Option<PathBuf> {
+        if path.is_relative() && !files.exists(path) {
+            search(path, include_paths, files)
+        } else {
+            None
+        }
+    }
+}
+
+/// Searches the directory of the including file, then the include paths in order,
+/// then asks `fallback`
+#[derive(Default)]
+pub struct RelativeIncludeResolver {
+    pub fallback: Option<Box<dyn Fn(&Path) -> Option<PathBuf>>>,
+}
+
+impl IncludeResolver for RelativeIncludeResolver {
+    fn resolve(
+        &self,
+        path: &Path,
+        includer: &Path,
+        include_paths: &[PathBuf],
+        files: &dyn FileProvider,
+    ) -> Option<PathBuf> {
+        if path.is_absolute() {
+            return None;
+        }
+        let relative = includer.parent().map(|x| x.join(path));
+        relative
+            .filter(|x| files.exists(x))
+            .or_else(|| search(path, include_paths, files))
+            .or_else(|| self.fallback.as_ref().and_then(|x| x(path)))
+    }
+}
+
+fn search(path: &Path, include_paths: &[PathBuf], files: &dyn FileProvider) -> Option<PathBuf> {
+    include_paths
+        .iter()
+        .map(|x| x.join(path))
+        .find(|x| files.exists(x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_resolver() {
+        let mut files = MemoryFileProvider::new();
+        files.insert("src/a.svh", String::from(""));
+        files.insert("inc/a.svh", String::from(""));
+        files.insert("inc/b.svh", String::from(""));
+
+        let include_paths = [PathBuf::from("inc")];
+        let includer = Path::new("src/top.sv");
+        let resolver = RelativeIncludeResolver::default();
+        let resolve = |x: &str| resolver.resolve(Path::new(x), includer, &include_paths, &files);
+        assert_eq!(resolve("a.svh"), Some(PathBuf::from("src/a.svh")));
+        assert_eq!(resolve("b.svh"), Some(PathBuf::from("inc/b.svh")));
+        assert_eq!(resolve("c.svh"), None);
+
+        let resolver = RelativeIncludeResolver {
+            fallback: Some(Box::new(|x| Some(Path::new("cache").join(x)))),
+        };
+        assert_eq!(
+            resolver.resolve(Path::new("c.svh"), includer, &include_paths, &files),
+            Some(PathBuf::from("cache/c.svh"))
+        );
+
+        let resolver = DefaultIncludeResolver;
+        assert_eq!(
+            resolver.resolve(Path::new("a.svh"), includer, &include_paths, &files),
+            Some(PathBuf::from("inc/a.svh"))
+        );
+    }
+}
@@ -0,0 +1,61 @@
+use std::fmt;
+use sv_parser_error::Location;
+
+/// Non-fatal issue found by the preprocessor
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Warning {
+    /// A macro is redefined with a different body.
+    /// `previous` is `None` if the previous definition is given by the caller.
+    Redefined {
+        name: String,
+        site: Location,
+        previous: Option<Location>,
+    },
+    /// `` `undef `` of a macro which isn't defined
+    UndefNotDefined { name: String, site: Location },
+    /// A macro is defined but never expanded nor tested by `` `ifdef ``
+    Unused { name: String, site: Location },
+    /// `` `ifdef `` or `` `ifndef `` of a macro which is never defined in the compilation
+    NeverDefined { name: String, site: Location },
+}
+
+impl Warning {
+    pub fn name(&self) -> &str {
+        match self {
+            Warning::Redefined { name, .. } => name,
+            Warning::UndefNotDefined { name, .. } => name,
+            Warning::Unused { name, .. } => name,
+            Warning::NeverDefined { name, .. } => name,
+        }
+    }
+
+    pub fn site(&self) -> &Location {
+        match self {
+            Warning::Redefined { site, .. } => site,
+            Warning::UndefNotDefined { site, .. } => site,
+            Warning::Unused { site, .. } => site,
+            Warning::NeverDefined { site, .. } => site,
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::Redefined { name, site, .. } => {
+                write!(
+                    f,
+                    "Macro redefined with a different body: {} at {}",
+                    name, site
+                )
+            }
+            Warning::UndefNotDefined { name, site } => {
+                write!(f, "Undef of undefined macro: {} at {}", name, site)
+            }
+            Warning::Unused { name, site } => write!(f, "Unused macro: {} at {}", name, site),
+            Warning::NeverDefined { name, site } => {
+                write!(f, "Macro never defined: {} at {}", name, site)
+            }
+        }
+    }
+}
@@ -0,0 +1,12 @@
+`define MACRO1(a=5,b="B",c) $display(a,,b,,c);
+`define MACRO2(a=5, b, c="C") $display(a,,b,,c);
+`define MACRO3(a=5, b=0, c="C") $display(a,,b,,c);
+module A;
+initial `MACRO1 ( , 2, 3 )
+initial `MACRO1 ( , 2, )
+initial `MACRO2 (1, , 3)
+initial `MACRO2 (, 2, )
+initial `MACRO2 (, 2)
+initial `MACRO3 ( 1 )
+initial `MACRO3 ( )
+endmodule
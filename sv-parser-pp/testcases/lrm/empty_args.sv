@@ -0,0 +1,8 @@
+`define D(x,y) initial $display("start", x , y, "end");
+module A;
+`D( "msg1" , "msg2" )
+`D( " msg1", )
+`D(, "msg2 ")
+`D(,)
+`D( , )
+endmodule
@@ -0,0 +1,12 @@
+`define str(x) "x is x"
+`define call(display) $display(display)
+`define num(h) 8'hff + h
+`define var_nand(dly) nand #dly
+`define comment(x) x // x
+module A;
+initial $display(`str(y));
+initial `call("hi");
+wire [7:0] a = `num(1);
+`var_nand(2) g121 (q21, n10, n11);
+wire `comment(b);
+endmodule
@@ -0,0 +1,2 @@
+`define MACRO3(a=5, b=0, c="C") $display(a,,b,,c);
+`MACRO3
@@ -0,0 +1,2 @@
+`define MACRO1(a=5,b="B",c) $display(a,,b,,c);
+`MACRO1 ( 1 )
@@ -0,0 +1,2 @@
+`define D(x,y) initial $display("start", x , y, "end");
+`D("msg1")
@@ -0,0 +1,8 @@
+`define append(f) f``_master
+`define cat(a, b) a``b
+`define s "a``b"
+module A;
+wire `append(clock);
+wire `cat(foo, bar);
+initial $display(`s);
+endmodule
@@ -0,0 +1,10 @@
+`define msg(x,y) `"x: `\`"y`\`"`"
+`define home(filename) `"/home/mydir/filename`"
+`define url(host) `"http://host/index`"
+`define name(p) `"p``_suffix`"
+module A;
+initial $display(`msg(left side,right side));
+initial $display(`home(myfile));
+initial $display(`url(example));
+initial $display(`name(top));
+endmodule
@@ -0,0 +1,3 @@
+module and_op (a, b, c);
+`include "test21.svh"
+endmodule
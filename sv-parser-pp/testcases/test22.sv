@@ -0,0 +1,4 @@
+module and_op (a, b, c);
+	wire	x;
+`include "test2.svh"
+endmodule
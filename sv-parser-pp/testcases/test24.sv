@@ -0,0 +1,26 @@
+`define A
+`define C
+`ifdef ((A && !B) || C)
+wire a;
+`endif
+`ifdef (B || !C)
+wire b;
+`elsif (A -> B)
+wire c;
+`elsif ((A <-> C) && !(B))
+wire d;
+`endif
+`ifndef (A && B)
+wire e;
+`endif
+`ifdef (B && A || C)
+wire f;
+`endif
+`ifdef (A || B -> B)
+wire g;
+`else
+wire h;
+`endif
+`ifdef (A)
+-> ev;
+`endif
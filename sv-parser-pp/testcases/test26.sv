@@ -0,0 +1,4 @@
+module and_op (a, b, c);
+`include "test2.svh"
+`include "test26.svh"
+endmodule
@@ -0,0 +1,13 @@
+`define A 1
+`define A 2
+`define B(x) x
+`define B(x) x
+`undef C
+`include "test27.svh"
+`ifdef D
+`endif
+`ifndef GUARD
+`endif
+module test;
+wire w = `A;
+endmodule
@@ -0,0 +1,10 @@
+module A;
+`include "test28.svh"
+`ifdef NOT_DEFINED
+wire x;
+`endif
+wire b = `W;
+`ifdef NOT_DEFINED
+wire y;
+`endif
+endmodule
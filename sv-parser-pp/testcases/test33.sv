@@ -0,0 +1,3 @@
+module A; // caf
+wire a;
+endmodule
@@ -0,0 +1,3 @@
+﻿module A;
+`include "test33_bom.svh"
+endmodule
@@ -0,0 +1,11 @@
+`define LOC `__FILE__, `__LINE__
+`define OUTER(x) x, `LOC
+module A;
+initial $display(`__FILE__, `__LINE__);
+initial $display(`LOC);
+initial $display(`OUTER(`__LINE__));
+`include "test36.svh"
+`line 100 "renamed.sv" 0
+initial $display(`__FILE__, `__LINE__);
+initial $display(`LOC);
+endmodule
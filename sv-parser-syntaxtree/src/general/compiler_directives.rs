@@ -131,9 +131,9 @@ pub struct IfdefDirective {
     pub nodes: (
         Symbol,
         Keyword,
-        TextMacroIdentifier,
+        IfdefCondition,
         IfdefGroupOfLines,
-        Vec<(Symbol, Keyword, TextMacroIdentifier, ElsifGroupOfLines)>,
+        Vec<(Symbol, Keyword, IfdefCondition, ElsifGroupOfLines)>,
         Option<(Symbol, Keyword, ElseGroupOfLines)>,
         Symbol,
         Keyword,
@@ -145,15 +145,44 @@ pub struct IfndefDirective {
     pub nodes: (
         Symbol,
         Keyword,
-        TextMacroIdentifier,
+        IfdefCondition,
         IfndefGroupOfLines,
-        Vec<(Symbol, Keyword, TextMacroIdentifier, ElsifGroupOfLines)>,
+        Vec<(Symbol, Keyword, IfdefCondition, ElsifGroupOfLines)>,
         Option<(Symbol, Keyword, ElseGroupOfLines)>,
         Symbol,
         Keyword,
     ),
 }
 
+#[derive(Clone, Debug, PartialEq, Node)]
+pub enum IfdefCondition {
+    TextMacroIdentifier(Box<TextMacroIdentifier>),
+    IfdefMacroExpression(Box<IfdefMacroExpression>),
+}
+
+#[derive(Clone, Debug, PartialEq, Node)]
+pub enum IfdefMacroExpression {
+    TextMacroIdentifier(Box<TextMacroIdentifier>),
+    Paren(Box<IfdefMacroExpressionParen>),
+    Not(Box<IfdefMacroExpressionNot>),
+    Binary(Box<IfdefMacroExpressionBinary>),
+}
+
+#[derive(Clone, Debug, PartialEq, Node)]
+pub struct IfdefMacroExpressionParen {
+    pub nodes: (Paren<IfdefMacroExpression>,),
+}
+
+#[derive(Clone, Debug, PartialEq, Node)]
+pub struct IfdefMacroExpressionNot {
+    pub nodes: (Symbol, IfdefMacroExpression),
+}
+
+#[derive(Clone, Debug, PartialEq, Node)]
+pub struct IfdefMacroExpressionBinary {
+    pub nodes: (IfdefMacroExpression, Symbol, IfdefMacroExpression),
+}
+
 #[derive(Clone, Debug, PartialEq, Node)]
 pub struct IfdefGroupOfLines {
     pub nodes: (Vec<SourceDescription>,),
@@ -45,6 +45,28 @@ impl Locate {
     pub fn str<'a, 'b>(&'a self, s: &'b str) -> &'b str {
         &s[self.offset..self.offset + self.len]
     }
+
+    /// Get 1-origin byte column in `s`
+    pub fn column(&self, s: &str) -> usize {
+        self.offset - line_begin(s, self.offset) + 1
+    }
+
+    /// Get 1-origin character column in `s`, where a tab advances to the next multiple of `tab_width`
+    pub fn char_column(&self, s: &str, tab_width: usize) -> usize {
+        let mut column = 0;
+        for c in s[line_begin(s, self.offset)..self.offset].chars() {
+            if c == '\t' && tab_width > 0 {
+                column += tab_width - column % tab_width;
+            } else {
+                column += 1;
+            }
+        }
+        column + 1
+    }
+}
+
+fn line_begin(s: &str, offset: usize) -> usize {
+    s[..offset].rfind('\n').map(|x| x + 1).unwrap_or(0)
 }
 
 // -----------------------------------------------------------------------------
@@ -17,6 +17,12 @@ pub enum WhiteSpace {
     Space(Box<Locate>),
     Comment(Box<Comment>),
     CompilerDirective(Box<CompilerDirective>),
+    ErrorText(Box<ErrorText>),
+}
+
+#[derive(Clone, Debug, PartialEq, Node)]
+pub struct ErrorText {
+    pub nodes: (Locate,),
 }
 
 #[derive(Clone, Debug, PartialEq)]
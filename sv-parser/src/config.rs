@@ -0,0 +1,433 @@
+use crate::{
+    unwrap_node, CellClause, ConfigDeclaration, ConfigRuleStatement, InstClause, LiblistClause,
+    RefNode, RefNodes, SyntaxTree, UseClause,
+};
+use std::collections::HashMap;
+use std::slice;
+
+/// Instance bound by a configuration
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Binding {
+    /// Hierarchical name of the instance like `top.u0.u1`
+    pub path: String,
+    /// Cell name in the instantiation
+    pub cell: String,
+    /// Library and cell which the instance is bound to. `None` if it isn't found.
+    pub target: Option<(String, String)>,
+    /// Parameter values overridden by `use` clauses
+    pub parameters: Vec<(String, String)>,
+}
+
+/// Cells of libraries and configurations used to resolve configuration bindings
+#[derive(Clone, Debug, Default)]
+pub struct Design {
+    libraries: Vec<String>,
+    // instance names and cell names in each library cell
+    cells: HashMap<(String, String), Vec<(String, String)>>,
+    configs: Vec<(String, Configuration)>,
+}
+
+#[derive(Clone, Debug)]
+struct Configuration {
+    name: String,
+    design: Vec<(Option<String>, String)>,
+    default: Option<Vec<String>>,
+    rules: Vec<(Selector, Action)>,
+}
+
+#[derive(Clone, Debug)]
+enum Selector {
+    Instance(String),
+    Cell(Option<String>, String),
+}
+
+#[derive(Clone, Debug)]
+enum Action {
+    Liblist(Vec<String>),
+    Use {
+        library: Option<String>,
+        cell: Option<String>,
+        parameters: Vec<(String, String)>,
+        config: bool,
+    },
+}
+
+impl Design {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add the cell `cell` of `library` containing `instances` of instance names and cell names.
+    /// Libraries are searched in the order in which they are first added if a configuration
+    /// doesn't have a `default` liblist, so add them in the order of the library map.
+    pub fn add_cell(&mut self, library: &str, cell: &str, instances: Vec<(String, String)>) {
+        if !self.libraries.iter().any(|x| x == library) {
+            self.libraries.push(String::from(library));
+        }
+        self.cells
+            .insert((String::from(library), String::from(cell)), instances);
+    }
+
+    /// Add modules, interfaces, programs and configurations in `syntax_tree` to `library`
+    pub fn add_syntax_tree(&mut self, library: &str, syntax_tree: &SyntaxTree) {
+        for node in syntax_tree {
+            let (cell, x) = match node {
+                RefNode::ModuleDeclaration(x) => (unwrap_node!(x, ModuleIdentifier), node),
+                RefNode::InterfaceDeclaration(x) => (unwrap_node!(x, InterfaceIdentifier), node),
+                RefNode::ProgramDeclaration(x) => (unwrap_node!(x, ProgramIdentifier), node),
+                RefNode::ConfigDeclaration(x) => {
+                    let config = configuration(syntax_tree, x);
+                    self.configs.push((String::from(library), config));
+                    continue;
+                }
+                _ => continue,
+            };
+            let cell = match cell.and_then(|x| syntax_tree.get_str_trim(vec![x])) {
+                Some(x) => x,
+                None => continue,
+            };
+
+            let mut instances = Vec::new();
+            for node in x {
+                if let RefNode::ModuleInstantiation(x) = node {
+                    let (ref name, _, ref list, _) = x.nodes;
+                    let name = String::from(syntax_tree.get_str_trim(name).unwrap());
+                    for instance in list.contents() {
+                        let (ref instance, _) = instance.nodes;
+                        let (ref instance, _) = instance.nodes;
+                        let instance = syntax_tree.get_str_trim(instance).unwrap();
+                        instances.push((String::from(instance), name.clone()));
+                    }
+                }
+            }
+            self.add_cell(library, cell, instances);
+        }
+    }
+
+    /// Resolve the bindings of all instances under the design cells of the configuration `name`
+    /// according to IEEE 1800-2017 33.4.
+    ///
+    /// An `instance` clause takes precedence over a `cell` clause, and a `cell` clause takes
+    /// precedence over the `default` liblist. The liblist selected by a clause is inherited by
+    /// the descendants of the instance, and `use ... :config` switches to the rules of the
+    /// hierarchical configuration. Returns `None` if the configuration isn't found.
+    pub fn resolve(&self, name: &str) -> Option<Vec<Binding>> {
+        let (library, config) = self.config(name)?;
+        let mut ret = Vec::new();
+        for (lib, cell) in &config.design {
+            let lib = lib.as_ref().unwrap_or(library);
+            let liblist = config.default.as_ref().unwrap_or(&self.libraries);
+            let target = self.find(slice::from_ref(lib), cell);
+            ret.push(Binding {
+                path: cell.clone(),
+                cell: cell.clone(),
+                target: target.clone(),
+                parameters: Vec::new(),
+            });
+            if let Some(target) = target {
+                let mut ancestors = vec![target.clone()];
+                self.bind_children(
+                    config,
+                    cell,
+                    cell,
+                    &target,
+                    liblist,
+                    &mut ancestors,
+                    &mut ret,
+                );
+            }
+        }
+        Some(ret)
+    }
+
+    fn config(&self, name: &str) -> Option<(&String, &Configuration)> {
+        self.configs
+            .iter()
+            .find(|(_, x)| x.name == name)
+            .map(|(library, x)| (library, x))
+    }
+
+    fn find(&self, liblist: &[String], cell: &str) -> Option<(String, String)> {
+        liblist
+            .iter()
+            .find(|x| self.cells.contains_key(&((*x).clone(), String::from(cell))))
+            .map(|x| (x.clone(), String::from(cell)))
+    }
+
+    // `local` is the hierarchical name from the design cell of `config`
+    #[allow(clippy::too_many_arguments)]
+    fn bind_children(
+        &self,
+        config: &Configuration,
+        local: &str,
+        path: &str,
+        target: &(String, String),
+        liblist: &[String],
+        ancestors: &mut Vec<(String, String)>,
+        ret: &mut Vec<Binding>,
+    ) {
+        for (instance, cell) in &self.cells[target] {
+            let local = format!("{}.{}", local, instance);
+            let path = format!("{}.{}", path, instance);
+            let default = self.find(liblist, cell);
+
+            let action = config
+                .rules
+                .iter()
+                .find(|(x, _)| matches!(x, Selector::Instance(x) if *x == local))
+                .or_else(|| {
+                    config.rules.iter().find(|(x, _)| match x {
+                        Selector::Cell(Some(lib), x) => {
+                            x == cell && default.as_ref().map(|x| &x.0) == Some(lib)
+                        }
+                        Selector::Cell(None, x) => x == cell,
+                        _ => false,
+                    })
+                })
+                .map(|(_, x)| x);
+
+            let mut config = config;
+            let mut local = local;
+            let mut liblist = liblist;
+            let mut parameters = Vec::new();
+            let target = match action {
+                Some(Action::Liblist(x)) => {
+                    liblist = x;
+                    self.find(liblist, cell)
+                }
+                Some(Action::Use {
+                    library,
+                    cell: name,
+                    parameters: x,
+                    config: true,
+                }) => {
+                    let name = name.as_ref().unwrap_or(cell);
+                    parameters = x.clone();
+                    match self.config(name) {
+                        Some((lib, x)) => {
+                            config = x;
+                            liblist = x.default.as_ref().unwrap_or(&self.libraries);
+                            x.design.first().and_then(|(l, x)| {
+                                local = x.clone();
+                                let l = l.as_ref().or(library.as_ref()).unwrap_or(lib);
+                                self.find(slice::from_ref(l), x)
+                            })
+                        }
+                        None => None,
+                    }
+                }
+                Some(Action::Use {
+                    library,
+                    cell: name,
+                    parameters: x,
+                    ..
+                }) => {
+                    let name = name.as_ref().unwrap_or(cell);
+                    parameters = x.clone();
+                    match library {
+                        Some(library) => self.find(slice::from_ref(library), name),
+                        None => self.find(liblist, name),
+                    }
+                }
+                None => default,
+            };
+
+            ret.push(Binding {
+                path: path.clone(),
+                cell: cell.clone(),
+                target: target.clone(),
+                parameters,
+            });
+            if let Some(target) = target {
+                if !ancestors.contains(&target) {
+                    ancestors.push(target.clone());
+                    self.bind_children(config, &local, &path, &target, liblist, ancestors, ret);
+                    ancestors.pop();
+                }
+            }
+        }
+    }
+}
+
+fn configuration(syntax_tree: &SyntaxTree, x: &ConfigDeclaration) -> Configuration {
+    let (_, ref name, _, _, ref design, ref rules, _, _) = x.nodes;
+    let (_, ref design, _) = design.nodes;
+    let design = design
+        .iter()
+        .map(|(lib, cell)| {
+            (
+                lib.as_ref().map(|(x, _)| text(syntax_tree, x)),
+                text(syntax_tree, cell),
+            )
+        })
+        .collect();
+
+    let liblist = |x: &LiblistClause| x.nodes.1.iter().map(|x| text(syntax_tree, x)).collect();
+    let cell = |x: &CellClause| {
+        let (_, ref lib, ref cell) = x.nodes;
+        Selector::Cell(
+            lib.as_ref().map(|(x, _)| text(syntax_tree, x)),
+            text(syntax_tree, cell),
+        )
+    };
+    let instance = |x: &InstClause| {
+        let (_, ref name) = x.nodes;
+        let (ref top, ref path) = name.nodes;
+        let mut ret = text(syntax_tree, top);
+        for (_, x) in path {
+            ret.push('.');
+            ret.push_str(&text(syntax_tree, x));
+        }
+        Selector::Instance(ret)
+    };
+    let use_clause = |x: &UseClause| {
+        let (library, cell, parameters, config) = match x {
+            UseClause::Cell(x) => {
+                let (_, ref lib, ref cell, ref config) = x.nodes;
+                (lib.as_ref(), Some(cell), None, config.is_some())
+            }
+            UseClause::Named(x) => {
+                let (_, ref parameters, ref config) = x.nodes;
+                (None, None, Some(parameters), config.is_some())
+            }
+            UseClause::CellNamed(x) => {
+                let (_, ref lib, ref cell, ref parameters, ref config) = x.nodes;
+                (lib.as_ref(), Some(cell), Some(parameters), config.is_some())
+            }
+        };
+        let parameters = parameters
+            .map(|x| {
+                x.contents()
+                    .into_iter()
+                    .map(|x| {
+                        let (_, ref name, ref value) = x.nodes;
+                        let value = syntax_tree.get_str_trim(&value.nodes.1).unwrap_or("");
+                        (text(syntax_tree, name), String::from(value))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Action::Use {
+            library: library.map(|(x, _)| text(syntax_tree, x)),
+            cell: cell.map(|x| text(syntax_tree, x)),
+            parameters,
+            config,
+        }
+    };
+
+    let mut default = None;
+    let mut ret = Vec::new();
+    for rule in rules {
+        match rule {
+            ConfigRuleStatement::Default(x) => default = Some(liblist(&x.nodes.1)),
+            ConfigRuleStatement::InstLib(x) => {
+                ret.push((instance(&x.nodes.0), Action::Liblist(liblist(&x.nodes.1))))
+            }
+            ConfigRuleStatement::InstUse(x) => {
+                ret.push((instance(&x.nodes.0), use_clause(&x.nodes.1)))
+            }
+            ConfigRuleStatement::CellLib(x) => {
+                ret.push((cell(&x.nodes.0), Action::Liblist(liblist(&x.nodes.1))))
+            }
+            ConfigRuleStatement::CellUse(x) => ret.push((cell(&x.nodes.0), use_clause(&x.nodes.1))),
+        }
+    }
+
+    Configuration {
+        name: text(syntax_tree, name),
+        design,
+        default,
+        rules: ret,
+    }
+}
+
+fn text<'a, T: Into<RefNodes<'a>>>(syntax_tree: &SyntaxTree, x: T) -> String {
+    String::from(syntax_tree.get_str_trim(x).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_lib_str, parse_sv_str};
+    use std::path::PathBuf;
+
+    fn binding(path: &str, cell: &str, target: Option<(&str, &str)>) -> Binding {
+        Binding {
+            path: String::from(path),
+            cell: String::from(cell),
+            target: target.map(|(x, y)| (String::from(x), String::from(y))),
+            parameters: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let rtl = r##"
+module top; adder a1(); adder a2(); foo f1(); endmodule
+module adder; lut l1(); endmodule
+module lut; endmodule
+module foo; endmodule
+"##;
+        let gate = r##"
+module adder; endmodule
+module lut; endmodule
+module foo; endmodule
+module bar; endmodule
+"##;
+        let lib = r##"
+config cfg1;
+    design rtlLib.top;
+    default liblist rtlLib gateLib;
+    instance top.a2 liblist gateLib;
+    cell foo use gateLib.bar .W(8);
+    cell lut liblist gateLib;
+endconfig
+config cfg2;
+    design rtlLib.top;
+    instance top.a1 use adder:config;
+endconfig
+config adder;
+    design rtlLib.adder;
+    default liblist gateLib;
+endconfig
+"##;
+        let defines = HashMap::new();
+        let parse = |x| parse_sv_str(x, PathBuf::from(""), &defines, &[""], false, false);
+        let (rtl, _) = parse(rtl).unwrap();
+        let (gate, _) = parse(gate).unwrap();
+        let (lib, _) =
+            parse_lib_str(lib, PathBuf::from(""), &defines, &[""], false, false).unwrap();
+
+        let mut design = Design::new();
+        design.add_syntax_tree("rtlLib", &rtl);
+        design.add_syntax_tree("gateLib", &gate);
+        design.add_syntax_tree("rtlLib", &lib);
+
+        let mut foo = binding("top.f1", "foo", Some(("gateLib", "bar")));
+        foo.parameters = vec![(String::from("W"), String::from("8"))];
+        assert_eq!(
+            design.resolve("cfg1").unwrap(),
+            vec![
+                binding("top", "top", Some(("rtlLib", "top"))),
+                binding("top.a1", "adder", Some(("rtlLib", "adder"))),
+                binding("top.a1.l1", "lut", Some(("gateLib", "lut"))),
+                binding("top.a2", "adder", Some(("gateLib", "adder"))),
+                foo.clone(),
+            ]
+        );
+
+        assert_eq!(
+            design.resolve("cfg2").unwrap(),
+            vec![
+                binding("top", "top", Some(("rtlLib", "top"))),
+                binding("top.a1", "adder", Some(("rtlLib", "adder"))),
+                binding("top.a1.l1", "lut", Some(("gateLib", "lut"))),
+                binding("top.a2", "adder", Some(("rtlLib", "adder"))),
+                binding("top.a2.l1", "lut", Some(("rtlLib", "lut"))),
+                binding("top.f1", "foo", Some(("rtlLib", "foo"))),
+            ]
+        );
+
+        assert_eq!(design.resolve("cfg3"), None);
+    }
+}
@@ -0,0 +1,450 @@
+use crate::{Error, Expansion, FileProvider, FileSystem, Location, PreprocessedText, Warning};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use sv_parser_error::expected_list;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const GREEN: &str = "\x1b[1;32m";
+const BLUE: &str = "\x1b[1;34m";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Label {
+    pub location: Location,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// The first label is the primary span
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: String) -> Self {
+        Diagnostic {
+            severity,
+            message,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_label(mut self, location: Location, message: String) -> Self {
+        self.labels.push(Label { location, message });
+        self
+    }
+
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    fn with_expansions(mut self, expansions: &[Expansion]) -> Self {
+        let mut i = 0;
+        while i < expansions.len() {
+            match &expansions[i] {
+                Expansion::Include(x) => {
+                    self = self.with_label(x.clone(), String::from("included from here"));
+                    i += 1;
+                }
+                Expansion::Macro(name) => {
+                    let count = expansions[i..]
+                        .iter()
+                        .take_while(|x| **x == expansions[i])
+                        .count();
+                    let note = if count > 1 {
+                        format!("in expansion of macro `{}` ({} times)", name, count)
+                    } else {
+                        format!("in expansion of macro `{}`", name)
+                    };
+                    self = self.with_note(note);
+                    i += count;
+                }
+            }
+        }
+        self
+    }
+}
+
+impl From<&Error> for Diagnostic {
+    fn from(err: &Error) -> Self {
+        let (message, label) = match err {
+            Error::Io(x) => (format!("IO error: {}", x), ""),
+            Error::File { source, path } => {
+                (format!("File error: {}: {}", path.display(), source), "")
+            }
+            Error::Include { source, location } => {
+                if source.location().is_some() {
+                    return Diagnostic::from(&**source);
+                }
+                let ret = Diagnostic::from(&**source);
+                let ret = Diagnostic::new(ret.severity, ret.message);
+                return match location {
+                    Some(x) => ret
+                        .with_label(x.location.clone(), String::from("included here"))
+                        .with_expansions(&x.expansions),
+                    None => ret,
+                };
+            }
+            Error::Parse { expected, rule, .. } => {
+                let message = match rule {
+                    Some(rule) => format!("Parse error in {}", rule),
+                    None => String::from("Parse error"),
+                };
+                if expected.is_empty() {
+                    (message, "")
+                } else {
+                    let ret = Diagnostic::new(Severity::Error, message);
+                    return match err.location() {
+                        Some(x) => ret
+                            .with_label(x.location.clone(), expected_list(expected))
+                            .with_expansions(&x.expansions),
+                        None => ret.with_note(expected_list(expected)),
+                    };
+                }
+            }
+            Error::Preprocess(_) => (String::from("Preprocess error"), ""),
+            Error::DefineArgNotFound(x, _) => (
+                format!("Define argument not found: {}", x),
+                "missing argument",
+            ),
+            Error::DefineNotFound(x, _) => (format!("Define not found: {}", x), "undefined macro"),
+            Error::DefineNoArgs(_) => (
+                String::from("Define must have argument"),
+                "arguments are required",
+            ),
+            Error::ExceedRecursiveLimit(_) => (
+                String::from("Exceed recursive limit"),
+                "expanded too deeply",
+            ),
+            Error::IncludeLine(_) => (String::from("Include line can't have other items"), ""),
+            Error::Filelist(x, _) => (format!("Filelist error: {}", x), ""),
+            Error::LibraryMap(x, _) => (format!("Library map error: {}", x), ""),
+            Error::DefineArg(x) => (format!("Invalid define: {:?}", x), ""),
+        };
+
+        let ret = Diagnostic::new(Severity::Error, message);
+        match err.location() {
+            Some(x) => ret
+                .with_label(x.location.clone(), String::from(label))
+                .with_expansions(&x.expansions),
+            None => ret,
+        }
+    }
+}
+
+impl From<&Warning> for Diagnostic {
+    fn from(warning: &Warning) -> Self {
+        let (message, label) = match warning {
+            Warning::Redefined { name, .. } => (
+                format!("Macro redefined with a different body: {}", name),
+                "redefined here",
+            ),
+            Warning::UndefNotDefined { name, .. } => {
+                (format!("Undef of undefined macro: {}", name), "")
+            }
+            Warning::Unused { name, .. } => (format!("Unused macro: {}", name), "defined here"),
+            Warning::NeverDefined { name, .. } => {
+                (format!("Macro never defined: {}", name), "tested here")
+            }
+        };
+
+        let ret = Diagnostic::new(Severity::Warning, message)
+            .with_label(warning.site().clone(), String::from(label));
+        match warning {
+            Warning::Redefined {
+                previous: Some(x), ..
+            } => ret.with_label(x.clone(), String::from("previous definition")),
+            _ => ret,
+        }
+    }
+}
+
+pub struct Renderer {
+    color: bool,
+    sources: HashMap<PathBuf, String>,
+    files: Box<dyn FileProvider>,
+}
+
+impl Renderer {
+    /// Renderer without colors
+    pub fn plain() -> Self {
+        Renderer {
+            color: false,
+            sources: HashMap::new(),
+            files: Box::new(FileSystem),
+        }
+    }
+
+    /// Renderer with ANSI colors
+    pub fn ansi() -> Self {
+        Renderer {
+            color: true,
+            sources: HashMap::new(),
+            files: Box::new(FileSystem),
+        }
+    }
+
+    /// Register the text of a source which isn't a file, e.g. given to `preprocess_str`
+    pub fn add_source<T: AsRef<Path>>(&mut self, path: T, text: String) {
+        self.sources.insert(PathBuf::from(path.as_ref()), text);
+    }
+
+    /// Set the provider reading the sources of `PreprocessedText`. It is `FileSystem` by default.
+    pub fn set_provider<T: FileProvider + 'static>(&mut self, files: T) {
+        self.files = Box::new(files);
+    }
+
+    pub fn render_error(&self, err: &Error, text: Option<&PreprocessedText>) -> String {
+        self.render(&Diagnostic::from(err), text)
+    }
+
+    pub fn render_warning(&self, warning: &Warning, text: Option<&PreprocessedText>) -> String {
+        self.render(&Diagnostic::from(warning), text)
+    }
+
+    /// Render the diagnostic with source snippets.
+    /// Sources are looked up in the registered sources, and the sources of `text` are read
+    /// through the provider. A label in an unknown source is rendered without a snippet.
+    pub fn render(&self, diagnostic: &Diagnostic, text: Option<&PreprocessedText>) -> String {
+        let mut files = HashMap::new();
+        for label in &diagnostic.labels {
+            let path = &label.location.path;
+            if !files.contains_key(path) {
+                let source = self
+                    .sources
+                    .get(path)
+                    .cloned()
+                    .or_else(|| text?.source(path, &*self.files));
+                files.insert(path.clone(), source);
+            }
+        }
+
+        let width = diagnostic
+            .labels
+            .iter()
+            .map(|x| format!("{}", x.location.line).len())
+            .max()
+            .unwrap_or(0);
+        let pad = " ".repeat(width);
+        let gutter = self.paint(BLUE, "|");
+
+        let mut ret = String::new();
+        let style = match diagnostic.severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+            Severity::Note => GREEN,
+        };
+        ret.push_str(&format!(
+            "{}{}\n",
+            self.paint(style, &format!("{}:", diagnostic.severity)),
+            self.paint(BOLD, &format!(" {}", diagnostic.message)),
+        ));
+
+        for (i, label) in diagnostic.labels.iter().enumerate() {
+            let arrow = if i == 0 { "-->" } else { ":::" };
+            let (style, caret) = if i == 0 { (style, "^") } else { (BLUE, "-") };
+            ret.push_str(&format!(
+                "{}{} {}\n",
+                pad,
+                self.paint(BLUE, arrow),
+                label.location
+            ));
+
+            let source = files.get(&label.location.path).and_then(|x| x.as_deref());
+            if let Some((line, prefix, span)) = source.and_then(|x| snippet(x, &label.location)) {
+                let number = format!("{:>width$}", label.location.line, width = width);
+                let marker = format!("{} {}", caret.repeat(span), label.message);
+                ret.push_str(&format!("{} {}\n", pad, gutter));
+                ret.push_str(&format!(
+                    "{} {} {}\n",
+                    self.paint(BLUE, &number),
+                    gutter,
+                    line
+                ));
+                ret.push_str(&format!(
+                    "{} {} {}{}\n",
+                    pad,
+                    gutter,
+                    prefix,
+                    self.paint(style, marker.trim_end())
+                ));
+            } else if !label.message.is_empty() {
+                ret.push_str(&format!("{} {} {}\n", pad, gutter, label.message));
+            }
+        }
+
+        if !diagnostic.notes.is_empty() {
+            ret.push_str(&format!("{} {}\n", pad, gutter));
+        }
+        for note in &diagnostic.notes {
+            ret.push_str(&format!(
+                "{} {} {}\n",
+                pad,
+                self.paint(BLUE, "="),
+                self.paint(BOLD, &format!("note: {}", note))
+            ));
+        }
+
+        ret
+    }
+
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            String::from(text)
+        }
+    }
+}
+
+// Returns the line of the location, the padding before the span and the span width.
+// The snippet is found by line and column because `location.begin` is the offset in
+// the physical file, which differs from `location.path` after a `` `line `` directive.
+fn snippet<'a>(source: &'a str, location: &Location) -> Option<(&'a str, String, usize)> {
+    let line_begin = match location.line {
+        0 | 1 => 0,
+        x => source.match_indices('\n').nth(x - 2)?.0 + 1,
+    };
+    let line_end = source[line_begin..]
+        .find('\n')
+        .map(|x| x + line_begin)
+        .unwrap_or(source.len());
+    let line = source[line_begin..line_end].trim_end_matches('\r');
+    let begin = line_begin + location.column.max(1) - 1;
+    let head = source.get(line_begin..begin)?;
+
+    let prefix: String = head
+        .chars()
+        .map(|x| if x == '\t' { '\t' } else { ' ' })
+        .collect();
+    let end = (begin + location.end.saturating_sub(location.begin))
+        .min(line_begin + line.len())
+        .max(begin);
+    let span = source
+        .get(begin..end)
+        .map(|x| x.chars().count())
+        .unwrap_or(0);
+
+    Some((line, prefix, span.max(1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_sv_str_recovery, preprocess_str, MemoryFileProvider};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_render() {
+        let src = "`define x `y\nmodule A;\n  wire a = `x;\nendmodule\n";
+        let err = preprocess_str(src, "test.sv", &HashMap::new(), &[""], false, false, 0)
+            .err()
+            .unwrap();
+
+        let mut renderer = Renderer::plain();
+        renderer.add_source("test.sv", String::from(src));
+        assert_eq!(
+            renderer.render_error(&err, None),
+            r##"error: Define not found: y
+ --> test.sv:3:12
+  |
+3 |   wire a = `x;
+  |            ^^ undefined macro
+  |
+  = note: in expansion of macro `x`
+"##
+        );
+    }
+
+    #[test]
+    fn test_render_recovery() {
+        let src = "module A;\n\twire a = ;\nendmodule\n";
+        let (syntax_tree, _, errors) =
+            parse_sv_str_recovery(src, "test.sv", &HashMap::new(), &[""], false).unwrap();
+        let text = syntax_tree.get_preprocessed_text();
+
+        let mut renderer = Renderer::plain();
+        renderer.add_source("test.sv", String::from(src));
+        assert!(renderer
+            .render_error(&errors[0], Some(text))
+            .starts_with("error: Parse error in net_declaration\n --> test.sv:2:11\n  |\n2 | \twire a = ;\n  | \t         ^ expected "));
+
+        let mut renderer = Renderer::ansi();
+        renderer.add_source("test.sv", String::from(src));
+        assert!(renderer
+            .render_error(&errors[0], Some(text))
+            .starts_with("\x1b[1;31merror:\x1b[0m\x1b[1m Parse error"));
+    }
+
+    #[test]
+    fn test_render_warning() {
+        let src = "`define x 1\n`define x 2\nmodule A;\n  wire a = `x;\nendmodule\n";
+        let (text, _) =
+            preprocess_str(src, "test.sv", &HashMap::new(), &[""], false, false, 0).unwrap();
+
+        // the source of the text is read through the provider
+        let mut files = MemoryFileProvider::new();
+        files.insert("test.sv", String::from(src));
+        let mut renderer = Renderer::plain();
+        renderer.set_provider(files);
+        assert_eq!(
+            renderer.render_warning(&text.warnings()[0], Some(&text)),
+            r##"warning: Macro redefined with a different body: x
+ --> test.sv:2:1
+  |
+2 | `define x 2
+  | ^^^^^^^^^^^ redefined here
+ ::: test.sv:1:1
+  |
+1 | `define x 1
+  | ----------- previous definition
+"##
+        );
+    }
+
+    #[test]
+    fn test_render_unknown_source() {
+        // the file exists, but isn't read because it isn't a known source
+        let path = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"));
+        let location = Location {
+            path: path.clone(),
+            begin: 0,
+            end: 9,
+            line: 1,
+            column: 1,
+        };
+        let diagnostic = Diagnostic::new(Severity::Error, String::from("Unknown"))
+            .with_label(location, String::from("here"));
+
+        let renderer = Renderer::plain();
+        assert_eq!(
+            renderer.render(&diagnostic, None),
+            format!("error: Unknown\n --> {}:1:1\n  | here\n", path.display())
+        );
+    }
+}
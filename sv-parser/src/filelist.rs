@@ -0,0 +1,386 @@
+use crate::{Define, Defines, Error, Location, SourceLocation};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Sources, include paths and defines collected from a filelist and its nested filelists
+#[derive(Clone, Debug, Default)]
+pub struct Filelist {
+    pub files: Vec<PathBuf>,
+    /// `+incdir+`
+    pub include_paths: Vec<PathBuf>,
+    /// `+define+`
+    pub defines: Defines,
+    /// `-v`
+    pub library_files: Vec<PathBuf>,
+    /// `-y`
+    pub library_dirs: Vec<PathBuf>,
+    /// `+libext+`
+    pub library_extensions: Vec<String>,
+    /// Arguments which aren't recognized
+    pub others: Vec<String>,
+}
+
+/// Parse the filelist at `path` like `-f`.
+/// Relative paths in `-f` filelists are relative to the current directory,
+/// and ones in `-F` filelists are relative to the directory of the filelist.
+pub fn parse_filelist<T: AsRef<Path>>(path: T) -> Result<Filelist, Error> {
+    parse_filelist_with_env(path, &|x| env::var(x).ok())
+}
+
+/// Parse the filelist `s` like `-f`. `path` is used for error locations.
+pub fn parse_filelist_str<T: AsRef<Path>>(s: &str, path: T) -> Result<Filelist, Error> {
+    parse_filelist_str_with_env(s, path, &|x| env::var(x).ok())
+}
+
+/// Same as `parse_filelist`, but environment variables are looked up through `env`.
+pub fn parse_filelist_with_env<T: AsRef<Path>>(
+    path: T,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Result<Filelist, Error> {
+    let mut ret = Filelist::default();
+    ret.add_filelist(path.as_ref(), false, env, &mut Vec::new())?;
+    Ok(ret)
+}
+
+/// Same as `parse_filelist_str`, but environment variables are looked up through `env`.
+pub fn parse_filelist_str_with_env<T: AsRef<Path>>(
+    s: &str,
+    path: T,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Result<Filelist, Error> {
+    let mut ret = Filelist::default();
+    ret.add_str(s, path.as_ref(), None, env, &mut Vec::new())?;
+    Ok(ret)
+}
+
+struct Token {
+    text: String,
+    begin: usize,
+    end: usize,
+}
+
+impl Filelist {
+    fn add_filelist(
+        &mut self,
+        path: &Path,
+        relative: bool,
+        env: &dyn Fn(&str) -> Option<String>,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<(), Error> {
+        let s = fs::read_to_string(path).map_err(|x| Error::File {
+            source: x,
+            path: PathBuf::from(path),
+        })?;
+        let base = if relative { path.parent() } else { None };
+        self.add_str(&s, path, base, env, stack)
+    }
+
+    fn add_str(
+        &mut self,
+        s: &str,
+        path: &Path,
+        base: Option<&Path>,
+        env: &dyn Fn(&str) -> Option<String>,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<(), Error> {
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        if stack.contains(&canonical) {
+            return Err(Error::Filelist(
+                format!("recursive filelist {}", path.display()),
+                None,
+            ));
+        }
+        stack.push(canonical);
+
+        let error = |message: String, token: &Token| {
+            let location = location(s, path, token.begin, token.end);
+            Error::Filelist(message, Some(Box::new(SourceLocation::new(location))))
+        };
+        let resolve = |x: &str| match base {
+            Some(base) if Path::new(x).is_relative() => base.join(x),
+            _ => PathBuf::from(x),
+        };
+
+        let mut tokens = tokenize(s).into_iter();
+        while let Some(token) = tokens.next() {
+            let arg = expand_env(&token.text, env)
+                .map_err(|x| error(format!("environment variable {} is not defined", x), &token))?;
+
+            match arg.as_str() {
+                "-f" | "-F" | "-v" | "-y" => {
+                    let value = tokens
+                        .next()
+                        .ok_or_else(|| error(format!("missing argument of {}", arg), &token))?;
+                    let value = expand_env(&value.text, env).map_err(|x| {
+                        error(format!("environment variable {} is not defined", x), &value)
+                    })?;
+                    let value = resolve(&value);
+                    match arg.as_str() {
+                        "-f" => self.add_filelist(&value, false, env, stack)?,
+                        "-F" => self.add_filelist(&value, true, env, stack)?,
+                        "-v" => self.library_files.push(value),
+                        _ => self.library_dirs.push(value),
+                    }
+                }
+                x if x.starts_with("+incdir+") => {
+                    for dir in plus_args(x, "+incdir+") {
+                        self.include_paths.push(resolve(dir));
+                    }
+                }
+                x if x.starts_with("+define+") => {
+                    for define in define_args(x) {
+                        let define = Define::parse(define)
+                            .map_err(|_| error(format!("invalid define {}", define), &token))?;
+                        self.defines.insert(define.identifier.clone(), Some(define));
+                    }
+                }
+                x if x.starts_with("+libext+") => {
+                    for ext in plus_args(x, "+libext+") {
+                        self.library_extensions.push(String::from(ext));
+                    }
+                }
+                x if x.starts_with('+') || x.starts_with('-') => {
+                    self.others.push(arg);
+                }
+                x => self.files.push(resolve(x)),
+            }
+        }
+
+        stack.pop();
+        Ok(())
+    }
+}
+
+fn plus_args<'a>(arg: &'a str, prefix: &str) -> impl Iterator<Item = &'a str> {
+    arg[prefix.len()..].split('+').filter(|x| !x.is_empty())
+}
+
+// Split `+define+` arguments at `+` outside parentheses and string literals,
+// e.g. `+define+MAX(a,b)=((a)+(b))+DEBUG`
+fn define_args(arg: &str) -> Vec<&str> {
+    let arg = &arg["+define+".len()..];
+    let mut ret = Vec::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut begin = 0;
+    for (i, c) in arg.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted && depth > 0 => depth -= 1,
+            '+' if !quoted && depth == 0 => {
+                ret.push(&arg[begin..i]);
+                begin = i + 1;
+            }
+            _ => (),
+        }
+    }
+    ret.push(&arg[begin..]);
+    ret.retain(|x| !x.is_empty());
+    ret
+}
+
+// Split into whitespace separated tokens without `//`, `#` and `/* */` comments.
+// Quoted whitespace doesn't separate tokens, and quotes surrounding a whole token are removed.
+fn tokenize(s: &str) -> Vec<Token> {
+    let mut ret = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some((begin, c)) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let next = chars.peek().map(|x| x.1);
+        if c == '#' || (c == '/' && next == Some('/')) {
+            for (_, c) in chars.by_ref() {
+                if c == '\n' {
+                    break;
+                }
+            }
+            continue;
+        }
+        if c == '/' && next == Some('*') {
+            chars.next();
+            let mut prev = ' ';
+            for (_, c) in chars.by_ref() {
+                if prev == '*' && c == '/' {
+                    break;
+                }
+                prev = c;
+            }
+            continue;
+        }
+
+        let mut text = String::new();
+        let mut quoted = c == '"';
+        let mut end = begin + c.len_utf8();
+        text.push(c);
+        while let Some(&(i, c)) = chars.peek() {
+            if c.is_whitespace() && !quoted {
+                break;
+            }
+            if c == '"' {
+                quoted = !quoted;
+            }
+            text.push(c);
+            end = i + c.len_utf8();
+            chars.next();
+        }
+        if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+            text = String::from(&text[1..text.len() - 1]);
+        }
+        ret.push(Token { text, begin, end });
+    }
+    ret
+}
+
+// Expand `$VAR`, `${VAR}` and `$(VAR)` with values from `env`.
+// The name of an undefined variable is returned as error.
+fn expand_env(s: &str, env: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut ret = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find('$') {
+        ret.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+
+        let (name, len) = match rest.chars().next() {
+            Some(open @ '{') | Some(open @ '(') => {
+                let close = if open == '{' { '}' } else { ')' };
+                match rest.find(close) {
+                    Some(x) => (&rest[1..x], x + 1),
+                    None => ("", 0),
+                }
+            }
+            _ => {
+                let x = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                (&rest[..x], x)
+            }
+        };
+
+        if name.is_empty() {
+            ret.push('$');
+        } else {
+            let value = env(name).ok_or_else(|| String::from(name))?;
+            ret.push_str(&value);
+            rest = &rest[len..];
+        }
+    }
+    ret.push_str(rest);
+    Ok(ret)
+}
+
+fn location(s: &str, path: &Path, begin: usize, end: usize) -> Location {
+    let head = &s[..begin];
+    let line_begin = head.rfind('\n').map(|x| x + 1).unwrap_or(0);
+    Location {
+        path: PathBuf::from(path),
+        begin,
+        end,
+        line: head.matches('\n').count() + 1,
+        column: begin - line_begin + 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn get_testcase(s: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testcases/filelist")
+            .join(s)
+    }
+
+    #[test]
+    fn test_parse_filelist_str() {
+        let vars: HashMap<&str, &str> = [("SV_PARSER_FILELIST_TEST", "/opt/ip")]
+            .iter()
+            .cloned()
+            .collect();
+        let env = |x: &str| vars.get(x).map(|x| String::from(*x));
+        let src = r##"
+// comment
++incdir+inc+$SV_PARSER_FILELIST_TEST/inc # comment
++define+WIDTH=8+DEBUG
++define+MSG="a b"
++define+MAX(a,b)=((a)+(b))+MIN(a,b)=((a)<(b)?(a):(b))
+/* block
+   comment */
+-v ${SV_PARSER_FILELIST_TEST}/lib/cells.v
+-y $(SV_PARSER_FILELIST_TEST)/lib +libext+.v+.sv
+"my dir/top.sv" -timescale=1ns/1ps
+"##;
+        let ret = parse_filelist_str_with_env(src, "test.f", &env).unwrap();
+        assert_eq!(ret.files, vec![PathBuf::from("my dir/top.sv")]);
+        assert_eq!(
+            ret.include_paths,
+            vec![PathBuf::from("inc"), PathBuf::from("/opt/ip/inc")]
+        );
+        assert_eq!(
+            ret.library_files,
+            vec![PathBuf::from("/opt/ip/lib/cells.v")]
+        );
+        assert_eq!(ret.library_dirs, vec![PathBuf::from("/opt/ip/lib")]);
+        assert_eq!(ret.library_extensions, vec![".v", ".sv"]);
+        assert_eq!(ret.others, vec!["-timescale=1ns/1ps"]);
+
+        let text = |x: &str| {
+            ret.defines[x]
+                .as_ref()
+                .unwrap()
+                .text
+                .as_ref()
+                .map(|x| x.text.clone())
+        };
+        assert_eq!(text("WIDTH"), Some(String::from("8")));
+        assert_eq!(text("DEBUG"), None);
+        assert_eq!(text("MSG"), Some(String::from("\"a b\"")));
+        assert_eq!(text("MAX"), Some(String::from("((a)+(b))")));
+        assert_eq!(text("MIN"), Some(String::from("((a)<(b)?(a):(b))")));
+        assert_eq!(ret.defines["MAX"].as_ref().unwrap().arguments.len(), 2);
+
+        let ret = parse_filelist_str_with_env(
+            "a.sv\n  $SV_PARSER_FILELIST_UNDEFINED/b.sv",
+            "test.f",
+            &env,
+        );
+        let err = ret.err().unwrap();
+        assert_eq!(
+            format!("{}", err),
+            "Filelist error: environment variable SV_PARSER_FILELIST_UNDEFINED is not defined at test.f:2:3"
+        );
+
+        let ret = parse_filelist_str("a.sv -f", "test.f");
+        assert!(matches!(ret, Err(Error::Filelist(_, Some(_)))));
+
+        let ret = parse_filelist_str("+define+URL=http://x", "test.f");
+        let err = ret.err().unwrap();
+        assert_eq!(
+            format!("{}", err),
+            "Filelist error: invalid define URL=http://x at test.f:1:1"
+        );
+    }
+
+    #[test]
+    fn test_parse_filelist() {
+        let ret = parse_filelist(get_testcase("top.f")).unwrap();
+        assert_eq!(
+            ret.files,
+            vec![
+                PathBuf::from("top.sv"),
+                PathBuf::from("testcases/filelist/sub/sub.sv"),
+                PathBuf::from("other.sv"),
+            ]
+        );
+        assert_eq!(
+            ret.include_paths,
+            vec![PathBuf::from("testcases/filelist/sub/inc")]
+        );
+
+        let ret = parse_filelist(get_testcase("loop.f"));
+        assert!(matches!(ret, Err(Error::Filelist(_, None))));
+    }
+}
@@ -1,17 +1,48 @@
 #![recursion_limit = "256"]
 
-use nom_greedyerror::error_position;
+mod config;
+mod diagnostic;
+mod filelist;
+mod library;
+mod recovery;
+mod variant;
+
+pub use config::{Binding, Design};
+pub use diagnostic::{Diagnostic, Label, Renderer, Severity};
+pub use filelist::{
+    parse_filelist, parse_filelist_str, parse_filelist_str_with_env, parse_filelist_with_env,
+    Filelist,
+};
+pub use library::{resolve_library_map, Library, LibraryMap};
+use nom::error::ErrorKind;
+use nom_greedyerror::{error_position, GreedyError, GreedyErrorKind};
+use recovery::Recovery;
 use std::fmt;
 use std::hash::BuildHasher;
 use std::path::{Path, PathBuf};
-pub use sv_parser_error::Error;
+pub use sv_parser_error::{Error, Expansion, Location, SourceLocation};
 use sv_parser_parser::{
-    lib_parser, lib_parser_incomplete, sv_parser, sv_parser_incomplete, Span, SpanInfo,
+    expected_tokens, lib_parser, lib_parser_incomplete, sv_parser, sv_parser_incomplete,
+    sv_parser_recovery_description, sv_parser_recovery_header, Span, SpanInfo,
 };
+pub use sv_parser_pp::cache::IncludeCache;
+pub use sv_parser_pp::conditional::{combinations, conditionals, Branch, Conditional};
+pub use sv_parser_pp::dependency::{json_manifest, make_rule, Dependency};
+pub use sv_parser_pp::encoding::{decode, Decoded, Encoding};
 pub use sv_parser_pp::preprocess::{
-    preprocess, preprocess_str, Define, DefineText, Defines, PreprocessedText,
+    load_defines, preprocess, preprocess_str, preprocess_str_with_provider, preprocess_with_cache,
+    preprocess_with_provider, Define, DefineText, Defines, InactiveRegion, MacroArgument,
+    MacroExpansion, OriginFrame, Position, PreprocessedText, SourceRange,
 };
+pub use sv_parser_pp::provider::{
+    DecodingFileSystem, DefaultIncludeResolver, FileProvider, FileSystem, IncludeResolver,
+    MemoryFileProvider, RelativeIncludeResolver,
+};
+pub use sv_parser_pp::warning::Warning;
 pub use sv_parser_syntaxtree::*;
+pub use variant::{parse_sv_variants, Variant, Variants};
+
+const RECOVERY_LIMIT: usize = 256;
 
 pub struct SyntaxTree {
     node: AnyNode,
@@ -73,6 +104,17 @@ impl SyntaxTree {
     pub fn get_origin(&self, locate: &Locate) -> Option<(&PathBuf, usize)> {
         self.text.origin(locate.offset)
     }
+
+    /// Get the original file, start and end positions of the specified `Locate`
+    pub fn get_source_range(&self, locate: &Locate, tab_width: usize) -> Option<SourceRange> {
+        self.text
+            .source_range(locate.offset, locate.offset + locate.len, tab_width)
+    }
+
+    /// Get the preprocessed text which the tree is parsed from
+    pub fn get_preprocessed_text(&self) -> &PreprocessedText {
+        &self.text
+    }
 }
 
 impl fmt::Display for SyntaxTree {
@@ -154,26 +196,122 @@ pub fn parse_sv_pp(
             },
             defines,
         )),
-        Err(x) => {
-            let pos = match x {
-                nom::Err::Incomplete(_) => None,
-                nom::Err::Error(e) => error_position(&e),
-                nom::Err::Failure(e) => error_position(&e),
-            };
-            let origin = if let Some(pos) = pos {
-                if let Some(origin) = text.origin(pos) {
-                    Some((origin.0.clone(), origin.1))
+        Err(x) => Err(parse_error(&text, &x)),
+    }
+}
+
+/// Parse with error recovery.
+/// Erroneous ranges are kept as `WhiteSpace::ErrorText` and the errors are returned with the tree.
+pub fn parse_sv_recovery<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
+    path: T,
+    pre_defines: &Defines<V>,
+    include_paths: &[U],
+    ignore_include: bool,
+) -> Result<(SyntaxTree, Defines, Vec<Error>), Error> {
+    let (text, defines) = preprocess(path, pre_defines, include_paths, false, ignore_include)?;
+    parse_sv_pp_recovery(text, defines)
+}
+
+pub fn parse_sv_pp_recovery(
+    text: PreprocessedText,
+    defines: Defines,
+) -> Result<(SyntaxTree, Defines, Vec<Error>), Error> {
+    let mut recovery = Recovery::new(text.text());
+    let mut errors = Vec::new();
+    let mut count = 0;
+
+    // Descriptions are parsed one by one, and a failure discards only the
+    // descriptions which may contain the new error range.
+    let span = Span::new_extra(text.text(), SpanInfo::default());
+    let mut header = None;
+    let mut descriptions = Vec::new();
+    loop {
+        let (s, result) = match (&header, descriptions.last()) {
+            (None, _) => match sv_parser_recovery_header(span, recovery.ranges()) {
+                Ok((s, x)) => {
+                    header = Some((s, x));
+                    continue;
+                }
+                Err(x) => (span, Err(x)),
+            },
+            (Some((s, (_, _, versions))), None) | (_, Some((s, _, versions))) => (
+                *s,
+                sv_parser_recovery_description(*s, recovery.ranges(), versions),
+            ),
+        };
+        match result {
+            Ok((s, Some((x, versions)))) => descriptions.push((s, x, versions)),
+            Ok((_, None)) => {
+                let (_, (a, b, _)) = header.unwrap();
+                let c = descriptions.into_iter().map(|(_, x, _)| x).collect();
+                let x = SourceText { nodes: (a, b, c) };
+                return Ok((
+                    SyntaxTree {
+                        node: x.into(),
+                        text,
+                    },
+                    defines,
+                    errors,
+                ));
+            }
+            Err(x) => {
+                if recovery.ranges() == [(0, text.text().len())] {
+                    return Err(parse_error(&text, &x));
+                }
+                let pos = parse_error_position(&x);
+                let ranges = recovery.ranges().to_vec();
+                count += 1;
+                let new_error = if count < RECOVERY_LIMIT {
+                    recovery.recover(pos)
                 } else {
-                    None
+                    recovery.give_up();
+                    false
+                };
+                if new_error {
+                    errors.push(parse_error(&text, &x));
+                }
+
+                // resume from the description before the first changed range,
+                // because an error text just after a description is its trailing white space
+                let begin = recovery
+                    .ranges()
+                    .iter()
+                    .filter(|x| !ranges.contains(x))
+                    .map(|(b, _)| *b)
+                    .min()
+                    .unwrap_or_else(|| s.location_offset());
+                while matches!(descriptions.last(), Some((s, _, _)) if s.location_offset() >= begin)
+                {
+                    descriptions.pop();
+                }
+                if matches!(&header, Some((s, _)) if descriptions.is_empty() && s.location_offset() >= begin)
+                {
+                    header = None;
                 }
-            } else {
-                None
-            };
-            Err(Error::Parse(origin))
+            }
         }
     }
 }
 
+pub fn parse_sv_str_recovery<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
+    s: &str,
+    path: T,
+    pre_defines: &Defines<V>,
+    include_paths: &[U],
+    ignore_include: bool,
+) -> Result<(SyntaxTree, Defines, Vec<Error>), Error> {
+    let (text, defines) = preprocess_str(
+        s,
+        path,
+        pre_defines,
+        include_paths,
+        ignore_include,
+        false,
+        0,
+    )?;
+    parse_sv_pp_recovery(text, defines)
+}
+
 pub fn parse_sv_str<T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
     s: &str,
     path: T,
@@ -244,23 +382,45 @@ pub fn parse_lib_pp(
             },
             defines,
         )),
-        Err(x) => {
-            let pos = match x {
-                nom::Err::Incomplete(_) => None,
-                nom::Err::Error(e) => error_position(&e),
-                nom::Err::Failure(e) => error_position(&e),
-            };
-            let origin = if let Some(pos) = pos {
-                if let Some(origin) = text.origin(pos) {
-                    Some((origin.0.clone(), origin.1))
-                } else {
-                    None
-                }
-            } else {
-                None
-            };
-            Err(Error::Parse(origin))
+        Err(x) => Err(parse_error(&text, &x)),
+    }
+}
+
+fn parse_error_position(err: &nom::Err<GreedyError<Span, ErrorKind>>) -> Option<usize> {
+    match err {
+        nom::Err::Incomplete(_) => None,
+        nom::Err::Error(e) => error_position(e),
+        nom::Err::Failure(e) => error_position(e),
+    }
+}
+
+fn parse_error(text: &PreprocessedText, err: &nom::Err<GreedyError<Span, ErrorKind>>) -> Error {
+    // token attempts may reach further than the position kept by `GreedyError`
+    let mut pos = parse_error_position(err);
+    let (expected, rule) = match expected_tokens() {
+        Some(x) if pos <= Some(x.pos) => {
+            pos = Some(x.pos);
+            (x.tokens, x.rule)
         }
+        _ => (Vec::new(), None),
+    };
+    let location = pos.and_then(|pos| text.source_location(pos)).map(Box::new);
+    let expected = expected.into_iter().map(String::from).collect();
+
+    // fall back to the innermost rule recorded in the error contexts
+    let rule = rule.or_else(|| match err {
+        nom::Err::Error(e) | nom::Err::Failure(e) => e.errors.iter().find_map(|(_, x)| match x {
+            GreedyErrorKind::Context(x) => Some(*x),
+            _ => None,
+        }),
+        nom::Err::Incomplete(_) => None,
+    });
+    let rule = rule.map(String::from);
+
+    Error::Parse {
+        location,
+        expected,
+        rule,
     }
 }
 
@@ -310,6 +470,180 @@ mod test {
         assert!(comment.is_some());
     }
 
+    #[test]
+    fn test_recovery() {
+        let src = r##"module A;
+  wire a = ;
+  wire b;
+endmodule
+
+module B;
+  always_comb begin
+    x = 1
+  end
+  function f
+  endfunction
+endmodule
+
+module C;
+endmodule
+"##;
+        let (syntax_tree, _, errors) =
+            parse_sv_str_recovery(src, PathBuf::from(""), &HashMap::new(), &[""], false).unwrap();
+        assert_eq!(errors.len(), 3);
+
+        let mut error_texts = vec![];
+        let mut modules = vec![];
+        for node in &syntax_tree {
+            match node {
+                RefNode::ErrorText(x) => error_texts.push(syntax_tree.get_str(x).unwrap()),
+                RefNode::ModuleIdentifier(x) => modules.push(syntax_tree.get_str(x).unwrap()),
+                _ => (),
+            }
+        }
+        assert_eq!(
+            error_texts,
+            vec!["wire a = ;", "x = 1", "function f\n  endfunction"]
+        );
+        assert_eq!(modules, vec!["A", "B", "C"]);
+
+        let src = "module A;\nendmodule\nmodule B;\n";
+        let (syntax_tree, _, errors) =
+            parse_sv_str_recovery(src, PathBuf::from(""), &HashMap::new(), &[""], false).unwrap();
+        assert_eq!(errors.len(), 1);
+        if let Some(RefNode::ErrorText(x)) = unwrap_node!(&syntax_tree, ErrorText) {
+            assert_eq!(syntax_tree.get_str(x), Some("module B;\n"));
+        } else {
+            unreachable!();
+        }
+
+        let src = "module A;\nendmodule\nwire = ;\nmodule B;\nendmodule\n";
+        let (syntax_tree, _, errors) =
+            parse_sv_str_recovery(src, PathBuf::from(""), &HashMap::new(), &[""], false).unwrap();
+        assert_eq!(errors.len(), 1);
+        let mut error_texts = vec![];
+        let mut modules = vec![];
+        for node in &syntax_tree {
+            match node {
+                RefNode::ErrorText(x) => error_texts.push(syntax_tree.get_str(x).unwrap()),
+                RefNode::ModuleIdentifier(x) => modules.push(syntax_tree.get_str(x).unwrap()),
+                _ => (),
+            }
+        }
+        assert_eq!(error_texts, vec!["wire = ;"]);
+        assert_eq!(modules, vec!["A", "B"]);
+
+        // keywords of `begin_keywords are kept through the descriptions
+        let src = r##"`begin_keywords "1364-1995"
+module A; wire logic; endmodule
+module B; wire logic; endmodule
+module C; wire logic; endmodule
+`end_keywords
+"##;
+        assert!(parse_sv_str(src, PathBuf::from(""), &HashMap::new(), &[""], false, false).is_ok());
+        let (_, _, errors) =
+            parse_sv_str_recovery(src, PathBuf::from(""), &HashMap::new(), &[""], false).unwrap();
+        assert!(errors.is_empty());
+
+        let src = src.replace("endmodule\nmodule C", "endmodule\nwire = ;\nmodule C");
+        let (syntax_tree, _, errors) =
+            parse_sv_str_recovery(&src, PathBuf::from(""), &HashMap::new(), &[""], false).unwrap();
+        assert_eq!(errors.len(), 1);
+        let mut error_texts = vec![];
+        let mut modules = vec![];
+        for node in &syntax_tree {
+            match node {
+                RefNode::ErrorText(x) => error_texts.push(syntax_tree.get_str(x).unwrap()),
+                RefNode::ModuleIdentifier(x) => modules.push(syntax_tree.get_str(x).unwrap()),
+                _ => (),
+            }
+        }
+        assert_eq!(error_texts, vec!["wire = ;"]);
+        assert_eq!(modules, vec!["A", "B", "C"]);
+    }
+
+    #[test]
+    fn test_expected() {
+        let src = "module A(); parameter A = 1 endmodule";
+        let ret = parse_sv_str(src, PathBuf::from(""), &HashMap::new(), &[""], false, false);
+        if let Err(Error::Parse {
+            location,
+            expected,
+            rule,
+        }) = ret
+        {
+            let location = location.unwrap().location;
+            assert_eq!(
+                (location.begin, location.line, location.column),
+                (28, 1, 29)
+            );
+            assert!(expected.contains(&String::from(";")));
+            assert!(expected.contains(&String::from(",")));
+            assert_eq!(rule.as_deref(), Some("parameter_declaration"));
+        } else {
+            unreachable!();
+        }
+
+        let src = "module A(input a b c); endmodule";
+        let ret = parse_sv_str(src, PathBuf::from(""), &HashMap::new(), &[""], false, false);
+        let err = ret.err().unwrap();
+        assert_eq!(
+            format!("{}", err),
+            "Parse error at :1:20, expected `[`, `=`, `,` or `)` in list_of_port_declarations"
+        );
+    }
+
+    #[test]
+    fn test_expected_in_macro() {
+        let src = "`define W wire a = ;\nmodule A;\n`W\nendmodule\n";
+        let ret = parse_sv_str(src, PathBuf::from(""), &HashMap::new(), &[""], false, false);
+        if let Err(Error::Parse { location, .. }) = ret {
+            let location = location.unwrap();
+            assert_eq!((location.location.line, location.location.column), (1, 20));
+            assert_eq!(
+                location.expansions,
+                vec![Expansion::Macro(String::from("W"))]
+            );
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[test]
+    fn test_source_range() {
+        let src = "module A;\n\twire\tabc;\nendmodule\n";
+        let (syntax_tree, _) = parse_sv_str(
+            src,
+            PathBuf::from("test.sv"),
+            &HashMap::new(),
+            &[""],
+            false,
+            false,
+        )
+        .unwrap();
+        let locate = match unwrap_node!(syntax_tree.into_iter(), NetDeclAssignment) {
+            Some(RefNode::NetDeclAssignment(x)) => unwrap_locate!(x).unwrap(),
+            _ => unreachable!(),
+        };
+        assert_eq!(locate.column(src), 7);
+        assert_eq!(locate.char_column(src, 4), 13);
+
+        let range = syntax_tree.get_source_range(locate, 4).unwrap();
+        assert_eq!(range.path, PathBuf::from("test.sv"));
+        assert_eq!(
+            (
+                range.begin.line,
+                range.begin.column,
+                range.begin.char_column
+            ),
+            (2, 7, 13)
+        );
+        assert_eq!(
+            (range.end.line, range.end.column, range.end.char_column),
+            (2, 10, 16)
+        );
+    }
+
     #[test]
     fn test_continuous() {
         let src = r##"`ifdef A
@@ -0,0 +1,340 @@
+use crate::{
+    parse_lib, unwrap_locate, Defines, Error, FilePathSpec, LibraryDeclaration, Locate, RefNode,
+    SourceLocation, SyntaxTree,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Files and include paths assigned to a library by `library` declarations
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Library {
+    pub name: String,
+    pub files: Vec<PathBuf>,
+    /// `-incdir`
+    pub include_paths: Vec<PathBuf>,
+}
+
+/// Libraries declared in a library map.
+/// Library maps included by `include` statements are expanded inline,
+/// so their libraries are listed here too.
+#[derive(Clone, Debug, Default)]
+pub struct LibraryMap {
+    /// Libraries in declaration order
+    pub libraries: Vec<Library>,
+}
+
+impl LibraryMap {
+    pub fn library(&self, name: &str) -> Option<&Library> {
+        self.libraries.iter().find(|x| x.name == name)
+    }
+
+    /// Get the name of the library which `path` is assigned to
+    pub fn library_of<T: AsRef<Path>>(&self, path: T) -> Option<&str> {
+        self.libraries
+            .iter()
+            .find(|x| x.files.iter().any(|x| x == path.as_ref()))
+            .map(|x| x.name.as_str())
+    }
+}
+
+/// Resolve the library map parsed by `parse_lib` to the files of each library.
+///
+/// File path specifications are relative to the library map containing them,
+/// and can have `?`, `*` and `...` wildcards or a trailing `/` for all files in the directory.
+/// If a file matches several specifications, an explicit file name takes precedence over
+/// a wildcard, and a wildcard takes precedence over a directory (IEEE 1800-2017 33.3.1.1).
+pub fn resolve_library_map(syntax_tree: &SyntaxTree) -> Result<LibraryMap, Error> {
+    let mut resolver = Resolver::default();
+    let mut stack = Vec::new();
+    if let Some(x) = unwrap_locate!(syntax_tree).and_then(|x| syntax_tree.get_origin(x)) {
+        stack.push(canonicalize(x.0));
+    }
+    resolver.add(syntax_tree, &mut stack)?;
+    Ok(resolver.finish())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    File,
+    Wildcard,
+    Directory,
+}
+
+#[derive(Default)]
+struct Resolver {
+    libraries: Vec<Library>,
+    // library index and precedence of the specification which each file is assigned by
+    assigns: HashMap<PathBuf, (usize, Precedence)>,
+    files: Vec<PathBuf>,
+}
+
+impl Resolver {
+    fn add(&mut self, syntax_tree: &SyntaxTree, stack: &mut Vec<PathBuf>) -> Result<(), Error> {
+        for node in syntax_tree {
+            match node {
+                RefNode::LibraryDeclaration(x) => self.add_library(syntax_tree, x)?,
+                RefNode::IncludeStatement(x) => {
+                    let (_, ref spec, _) = x.nodes;
+                    let (path, locate) = file_path_spec(syntax_tree, spec);
+                    let canonical = canonicalize(&path);
+                    if stack.contains(&canonical) {
+                        return Err(Error::LibraryMap(
+                            format!("recursive include of {}", path.display()),
+                            location(syntax_tree, locate),
+                        ));
+                    }
+
+                    let defines: Defines = HashMap::new();
+                    let include_paths: [PathBuf; 0] = [];
+                    let (include, _) = parse_lib(&path, &defines, &include_paths, false, false)?;
+                    stack.push(canonical);
+                    self.add(&include, stack)?;
+                    stack.pop();
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn add_library(
+        &mut self,
+        syntax_tree: &SyntaxTree,
+        x: &LibraryDeclaration,
+    ) -> Result<(), Error> {
+        let (_, ref name, ref specs, ref incdirs, _) = x.nodes;
+        let name = syntax_tree.get_str_trim(name).unwrap();
+        let index = match self.libraries.iter().position(|x| x.name == name) {
+            Some(x) => x,
+            None => {
+                self.libraries.push(Library {
+                    name: String::from(name),
+                    ..Default::default()
+                });
+                self.libraries.len() - 1
+            }
+        };
+
+        if let Some((_, incdirs)) = incdirs {
+            for spec in incdirs.contents() {
+                let (path, _) = file_path_spec(syntax_tree, spec);
+                self.libraries[index].include_paths.push(path);
+            }
+        }
+
+        for spec in specs.contents() {
+            let (path, locate) = file_path_spec(syntax_tree, spec);
+            let pattern = path.to_string_lossy();
+            let precedence = if pattern.ends_with('/') {
+                Precedence::Directory
+            } else if pattern.contains(&['*', '?'][..]) || pattern.contains("...") {
+                Precedence::Wildcard
+            } else {
+                Precedence::File
+            };
+
+            for file in expand(&pattern) {
+                match self.assigns.get(&file) {
+                    Some((i, p)) if *p < precedence || (*p == precedence && *i == index) => (),
+                    Some((i, p)) if *p == precedence => {
+                        return Err(Error::LibraryMap(
+                            format!(
+                                "{} matches both library {} and {}",
+                                file.display(),
+                                self.libraries[*i].name,
+                                name
+                            ),
+                            location(syntax_tree, locate),
+                        ));
+                    }
+                    Some(_) => {
+                        self.assigns.insert(file, (index, precedence));
+                    }
+                    None => {
+                        self.files.push(file.clone());
+                        self.assigns.insert(file, (index, precedence));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> LibraryMap {
+        for file in self.files {
+            let (index, _) = self.assigns[&file];
+            self.libraries[index].files.push(file);
+        }
+        LibraryMap {
+            libraries: self.libraries,
+        }
+    }
+}
+
+// Get the path of the specification relative to the library map containing it
+fn file_path_spec<'a>(syntax_tree: &SyntaxTree, x: &'a FilePathSpec) -> (PathBuf, &'a Locate) {
+    let locate = unwrap_locate!(x).unwrap();
+    let mut text = syntax_tree.get_str_trim(x).unwrap();
+    if let FilePathSpec::Literal(_) = x {
+        text = &text[1..text.len() - 1];
+    }
+    let path = match syntax_tree.get_origin(locate) {
+        Some((origin, _)) if Path::new(text).is_relative() => match origin.parent() {
+            Some(dir) => dir.join(text),
+            None => PathBuf::from(text),
+        },
+        _ => PathBuf::from(text),
+    };
+    (path, locate)
+}
+
+fn location(syntax_tree: &SyntaxTree, locate: &Locate) -> Option<Box<SourceLocation>> {
+    syntax_tree
+        .get_preprocessed_text()
+        .source_location(locate.offset)
+        .map(Box::new)
+}
+
+fn canonicalize(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path))
+}
+
+fn expand(pattern: &str) -> Vec<PathBuf> {
+    let mut pattern = String::from(pattern);
+    if pattern.ends_with('/') {
+        pattern.push('*');
+    }
+    let dir = if pattern.starts_with('/') {
+        PathBuf::from("/")
+    } else {
+        PathBuf::new()
+    };
+    let components: Vec<_> = pattern
+        .split('/')
+        .filter(|x| !x.is_empty() && *x != ".")
+        .collect();
+
+    let mut ret = Vec::new();
+    walk(&dir, &components, &mut ret);
+    ret
+}
+
+fn walk(dir: &Path, components: &[&str], ret: &mut Vec<PathBuf>) {
+    match components.split_first() {
+        None => {
+            if dir.is_file() {
+                ret.push(PathBuf::from(dir));
+            }
+        }
+        Some((&"...", rest)) => {
+            walk(dir, rest, ret);
+            for entry in entries(dir) {
+                if entry.is_dir() {
+                    walk(&entry, components, ret);
+                }
+            }
+        }
+        Some((x, rest)) if x.contains(&['*', '?'][..]) => {
+            let pattern: Vec<_> = x.chars().collect();
+            for entry in entries(dir) {
+                if let Some(name) = entry.file_name() {
+                    let name: Vec<_> = name.to_string_lossy().chars().collect();
+                    if wildcard(&pattern, &name) {
+                        walk(&entry, rest, ret);
+                    }
+                }
+            }
+        }
+        Some((x, rest)) => walk(&dir.join(x), rest, ret),
+    }
+}
+
+fn entries(dir: &Path) -> Vec<PathBuf> {
+    let read_dir = if dir.as_os_str().is_empty() {
+        fs::read_dir(".")
+    } else {
+        fs::read_dir(dir)
+    };
+    let mut ret: Vec<_> = match read_dir {
+        Ok(x) => x
+            .filter_map(|x| x.ok())
+            .map(|x| dir.join(x.file_name()))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    ret.sort();
+    ret
+}
+
+fn wildcard(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some(('*', p)), _) => {
+            wildcard(p, name) || (!name.is_empty() && wildcard(pattern, &name[1..]))
+        }
+        (Some(('?', p)), Some((_, n))) => wildcard(p, n),
+        (Some((a, p)), Some((b, n))) if a == b => wildcard(p, n),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_lib_str;
+
+    #[test]
+    fn test_resolve_library_map() {
+        let path = PathBuf::from("testcases/library/lib.map");
+        let defines = HashMap::new();
+        let (syntax_tree, _) = parse_lib(&path, &defines, &[""], false, false).unwrap();
+        let map = resolve_library_map(&syntax_tree).unwrap();
+
+        let names: Vec<_> = map.libraries.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, vec!["rtlLib", "gateLib", "topLib", "subLib"]);
+        let files = |x: &str| -> Vec<_> {
+            map.library(x)
+                .unwrap()
+                .files
+                .iter()
+                .map(|x| x.to_string_lossy().into_owned())
+                .collect()
+        };
+        assert_eq!(files("rtlLib"), vec!["testcases/library/rtl/alu.v"]);
+        assert_eq!(
+            files("gateLib"),
+            vec![
+                "testcases/library/rtl/alu.vg",
+                "testcases/library/gate/cell.v",
+                "testcases/library/gate/x/y/cell.v",
+            ]
+        );
+        assert_eq!(files("topLib"), vec!["testcases/library/rtl/top.v"]);
+        assert_eq!(files("subLib"), vec!["testcases/library/sub/a.sv"]);
+        assert_eq!(
+            map.library("rtlLib").unwrap().include_paths,
+            vec![PathBuf::from("testcases/library/inc")]
+        );
+        assert_eq!(
+            map.library_of("testcases/library/rtl/top.v"),
+            Some("topLib")
+        );
+
+        let src = "library a rtl/;\nlibrary b rtl/top.v, rtl/;";
+        let (syntax_tree, _) = parse_lib_str(
+            src,
+            "testcases/library/conflict.map",
+            &defines,
+            &[""],
+            false,
+            false,
+        )
+        .unwrap();
+        let err = resolve_library_map(&syntax_tree).unwrap_err();
+        assert_eq!(
+            format!("{}", err),
+            "Library map error: testcases/library/rtl/alu.v matches both library a and b at testcases/library/conflict.map:2:22"
+        );
+    }
+}
@@ -0,0 +1,316 @@
+// Each parse failure marks a range of the preprocessed text as `ErrorText`,
+// and the text is parsed again from the description containing the range.
+// The range starts at a statement boundary and ends at the next `;` or block
+// keyword. If a failure occurs in the same range again, the range is expanded to the enclosing
+// function/task/class/design element, and finally to the whole text.
+
+const BLOCK_BEGIN: &[&str] = &["begin", "fork"];
+
+const BLOCK_END: &[&str] = &[
+    "end",
+    "endcase",
+    "endchecker",
+    "endclass",
+    "endclocking",
+    "endconfig",
+    "endfunction",
+    "endgenerate",
+    "endgroup",
+    "endinterface",
+    "endmodule",
+    "endpackage",
+    "endprimitive",
+    "endprogram",
+    "endproperty",
+    "endsequence",
+    "endspecify",
+    "endtable",
+    "endtask",
+    "join",
+    "join_any",
+    "join_none",
+];
+
+const ELEMENTS: &[(&str, &str)] = &[
+    ("function", "endfunction"),
+    ("task", "endtask"),
+    ("class", "endclass"),
+    ("module", "endmodule"),
+    ("macromodule", "endmodule"),
+    ("interface", "endinterface"),
+    ("program", "endprogram"),
+    ("package", "endpackage"),
+    ("primitive", "endprimitive"),
+    ("config", "endconfig"),
+    ("checker", "endchecker"),
+];
+
+pub(crate) struct Recovery<'a> {
+    text: &'a str,
+    tokens: Vec<(usize, usize)>,
+    ranges: Vec<(usize, usize)>,
+}
+
+impl<'a> Recovery<'a> {
+    pub(crate) fn new(text: &'a str) -> Self {
+        Recovery {
+            text,
+            tokens: tokenize(text),
+            ranges: Vec::new(),
+        }
+    }
+
+    pub(crate) fn ranges(&self) -> &[(usize, usize)] {
+        &self.ranges
+    }
+
+    /// Add an error range for a parse failure at `pos`.
+    /// Returns `false` if the failure is caused by a previous error range.
+    pub(crate) fn recover(&mut self, pos: Option<usize>) -> bool {
+        let pos = if let Some(pos) = pos {
+            pos
+        } else {
+            self.give_up();
+            return true;
+        };
+
+        let (begin, end) = self.statement_range(pos);
+        let overlapped = self.ranges.iter().position(|&(b, e)| {
+            (b <= pos && pos < e)
+                || (b < end && begin < e)
+                || (begin == end && b <= begin && begin <= e)
+        });
+
+        // no token between the previous range and the statement
+        let adjacent = self.ranges.iter().position(|&(_, e)| {
+            e <= begin
+                && self.tokens.partition_point(|(b, _)| *b < e)
+                    == self.tokens.partition_point(|(b, _)| *b < begin)
+        });
+
+        if let Some(i) = overlapped {
+            let (b, e) = self.ranges[i];
+            let range = self.element_range(b.min(begin), e.max(end));
+            self.insert(range);
+            false
+        } else if let (Some(i), true) = (adjacent, begin < end) {
+            self.insert((self.ranges[i].0, end));
+            false
+        } else if begin == end {
+            let range = self.element_range(begin, end);
+            self.insert(range);
+            true
+        } else {
+            self.insert((begin, end));
+            true
+        }
+    }
+
+    pub(crate) fn give_up(&mut self) {
+        self.ranges = vec![(0, self.text.len())];
+    }
+
+    fn word(&self, i: usize) -> &str {
+        let (begin, end) = self.tokens[i];
+        &self.text[begin..end]
+    }
+
+    fn is_sync(&self, i: usize) -> bool {
+        let x = self.word(i);
+        x == ";" || BLOCK_BEGIN.contains(&x) || BLOCK_END.contains(&x)
+    }
+
+    fn statement_range(&self, pos: usize) -> (usize, usize) {
+        let floor = self
+            .ranges
+            .iter()
+            .filter(|(_, e)| *e <= pos)
+            .map(|(_, e)| *e)
+            .max()
+            .unwrap_or(0);
+
+        // the token just before `pos` may be consumed wrongly
+        let mut i = self.tokens.partition_point(|(_, e)| *e < pos);
+        if i < self.tokens.len() && self.tokens[i].1 == pos && self.word(i) == ";" {
+            i += 1;
+        }
+
+        let mut k = i;
+        while k > 0 && !self.is_sync(k - 1) && self.tokens[k - 1].0 >= floor {
+            k -= 1;
+        }
+        let begin = self.token_begin(k);
+
+        let mut end = self.text.len();
+        for j in i..self.tokens.len() {
+            let x = self.word(j);
+            if x == ";" {
+                end = self.tokens[j].1;
+                break;
+            } else if BLOCK_BEGIN.contains(&x) || BLOCK_END.contains(&x) {
+                end = if j == k {
+                    self.tokens[j].1
+                } else {
+                    self.tokens[j - 1].1
+                };
+                break;
+            }
+        }
+
+        (begin, end.max(begin))
+    }
+
+    fn element_range(&self, begin: usize, end: usize) -> (usize, usize) {
+        let i = self.tokens.partition_point(|(b, _)| *b < begin);
+
+        let mut ret = None;
+        for (start, finish) in ELEMENTS {
+            let mut s = None;
+            for k in (0..i).rev() {
+                let x = self.word(k);
+                if x == *finish {
+                    break;
+                } else if x == *start && !self.is_declaration(k) {
+                    s = Some(k);
+                    break;
+                }
+            }
+            let s = if let Some(s) = s { s } else { continue };
+
+            let mut k = s;
+            while k > 0 && !self.is_sync(k - 1) {
+                k -= 1;
+            }
+            let b = self.token_begin(k);
+
+            let mut e = self.text.len();
+            for k in i..self.tokens.len() {
+                if self.word(k) == *finish && self.tokens[k].1 >= end {
+                    e = self.tokens[k].1;
+                    if k + 2 < self.tokens.len() && self.word(k + 1) == ":" {
+                        e = self.tokens[k + 2].1;
+                    }
+                    break;
+                }
+            }
+
+            if (b, e) != (begin, end) && b <= begin && end <= e {
+                ret = match ret {
+                    Some((rb, re)) if re - rb <= e - b => Some((rb, re)),
+                    _ => Some((b, e)),
+                };
+            }
+        }
+
+        if let Some(x) = ret {
+            x
+        } else if end < self.text.len() {
+            (begin, self.text.len())
+        } else {
+            (0, self.text.len())
+        }
+    }
+
+    // `virtual interface`, `typedef class`, `extern function` and so on
+    // don't have a corresponding end keyword.
+    fn is_declaration(&self, i: usize) -> bool {
+        let prev = if i > 0 { self.word(i - 1) } else { "" };
+        match self.word(i) {
+            "interface" => prev == "virtual" || self.word_at(i + 1) == "class",
+            "class" => prev == "typedef",
+            "function" | "task" => {
+                let mut k = i;
+                while k > 0 && !self.is_sync(k - 1) {
+                    k -= 1;
+                    if ["extern", "pure", "import", "export"].contains(&self.word(k)) {
+                        return true;
+                    }
+                }
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn word_at(&self, i: usize) -> &str {
+        if i < self.tokens.len() {
+            self.word(i)
+        } else {
+            ""
+        }
+    }
+
+    fn token_begin(&self, i: usize) -> usize {
+        if i < self.tokens.len() {
+            self.tokens[i].0
+        } else {
+            self.text.len()
+        }
+    }
+
+    fn insert(&mut self, range: (usize, usize)) {
+        let (mut begin, mut end) = range;
+        let mut ranges = Vec::new();
+        for &(b, e) in &self.ranges {
+            if b <= end && begin <= e {
+                begin = begin.min(b);
+                end = end.max(e);
+            } else {
+                ranges.push((b, e));
+            }
+        }
+        ranges.push((begin, end));
+        ranges.sort_unstable();
+
+        // give up if no progress
+        if begin >= end || ranges == self.ranges {
+            self.give_up();
+        } else {
+            self.ranges = ranges;
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Vec<(usize, usize)> {
+    let bytes = s.as_bytes();
+    let is_word = |c: u8| c.is_ascii_alphanumeric() || c == b'_' || c == b'$';
+
+    let mut ret = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let c = bytes[pos];
+        let begin = pos;
+        if c.is_ascii_whitespace() {
+            pos += 1;
+            continue;
+        } else if s[pos..].starts_with("//") {
+            pos = s[pos..].find('\n').map(|x| pos + x).unwrap_or(bytes.len());
+            continue;
+        } else if s[pos..].starts_with("/*") {
+            pos = s[pos + 2..]
+                .find("*/")
+                .map(|x| pos + x + 4)
+                .unwrap_or(bytes.len());
+            continue;
+        } else if c == b'"' {
+            pos += 1;
+            while pos < bytes.len() && bytes[pos] != b'"' {
+                pos += if bytes[pos] == b'\\' { 2 } else { 1 };
+            }
+            pos = (pos + 1).min(bytes.len());
+        } else if c == b'\\' {
+            while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+        } else if is_word(c) || c == b'`' {
+            pos += 1;
+            while pos < bytes.len() && is_word(bytes[pos]) {
+                pos += 1;
+            }
+        } else {
+            pos += s[pos..].chars().next().map(|x| x.len_utf8()).unwrap_or(1);
+        }
+        ret.push((begin, pos));
+    }
+    ret
+}
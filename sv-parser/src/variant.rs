@@ -0,0 +1,206 @@
+use crate::{
+    combinations, parse_sv_pp, preprocess_with_cache, Defines, Error, FileProvider, IncludeCache,
+    IncludeResolver,
+};
+use std::collections::HashSet;
+use std::hash::BuildHasher;
+use std::path::{Path, PathBuf};
+use std::vec;
+
+/// Result of parsing a source file with a combination of the chosen macros
+#[derive(Debug)]
+pub struct Variant {
+    /// Chosen macros defined in this variant. The others are undefined.
+    pub defined: Vec<String>,
+    /// Error of preprocessing or parsing, or `None` if the variant is parsed successfully
+    pub error: Option<Error>,
+}
+
+/// Iterator over the variants returned by `parse_sv_variants`
+pub struct Variants<'a, U: AsRef<Path>> {
+    path: PathBuf,
+    pre_defines: Defines,
+    include_paths: &'a [U],
+    ignore_include: bool,
+    files: &'a dyn FileProvider,
+    resolver: &'a dyn IncludeResolver,
+    cache: IncludeCache,
+    macros: Vec<String>,
+    // defined state of each macro in the remaining combinations
+    combinations: vec::IntoIter<Vec<bool>>,
+    // inactive regions of the variants already returned
+    seen: HashSet<Vec<(PathBuf, usize)>>,
+}
+
+/// Parse `path` read by `files` with the combinations of defining and undefining `macros`.
+///
+/// Only the combinations taking distinct branches of the conditional directives in `path`
+/// are preprocessed (see `combinations`), and a combination selecting the same active regions
+/// as a previous one is skipped.
+/// The macros of `pre_defines` which aren't in `macros` are defined in all variants.
+/// The macros tested in a file can be found by `conditionals`.
+pub fn parse_sv_variants<'a, T: AsRef<Path>, U: AsRef<Path>, V: BuildHasher>(
+    path: T,
+    pre_defines: &Defines<V>,
+    include_paths: &'a [U],
+    ignore_include: bool,
+    macros: &[&str],
+    files: &'a dyn FileProvider,
+    resolver: &'a dyn IncludeResolver,
+) -> Result<Variants<'a, U>, Error> {
+    let path = PathBuf::from(path.as_ref());
+    let decoded = files.read_decoded(&path).map_err(|x| Error::File {
+        source: x,
+        path: path.clone(),
+    })?;
+    let combinations = combinations(&decoded.text, &path, pre_defines, macros)?;
+
+    let mut defines = Defines::new();
+    for (k, v) in pre_defines {
+        if !macros.contains(&k.as_str()) {
+            defines.insert(k.clone(), v.clone());
+        }
+    }
+    Ok(Variants {
+        path,
+        pre_defines: defines,
+        include_paths,
+        ignore_include,
+        files,
+        resolver,
+        cache: IncludeCache::new(),
+        macros: macros.iter().map(|x| String::from(*x)).collect(),
+        combinations: combinations.into_iter(),
+        seen: HashSet::new(),
+    })
+}
+
+impl<'a, U: AsRef<Path>> Iterator for Variants<'a, U> {
+    type Item = Variant;
+
+    fn next(&mut self) -> Option<Variant> {
+        loop {
+            let combination = self.combinations.next()?;
+            let mut defines = self.pre_defines.clone();
+            let mut defined = Vec::new();
+            for (name, x) in self.macros.iter().zip(combination) {
+                if x {
+                    defines.insert(name.clone(), None);
+                    defined.push(name.clone());
+                }
+            }
+
+            let (text, defines) = match preprocess_with_cache(
+                &self.path,
+                &defines,
+                self.include_paths,
+                false,
+                self.ignore_include,
+                self.files,
+                self.resolver,
+                &self.cache,
+            ) {
+                Ok(x) => x,
+                Err(x) => {
+                    return Some(Variant {
+                        defined,
+                        error: Some(x),
+                    })
+                }
+            };
+
+            let regions = text
+                .inactive_regions()
+                .iter()
+                .map(|x| (x.location.path.clone(), x.location.begin))
+                .collect();
+            if self.seen.insert(regions) {
+                return Some(Variant {
+                    defined,
+                    error: parse_sv_pp(text, defines, false).err(),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{conditionals, DefaultIncludeResolver, FileSystem, MemoryFileProvider};
+    use std::collections::HashMap;
+    use std::fs;
+
+    #[test]
+    fn test_parse_sv_variants() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testcases/variant.sv");
+        let text = fs::read_to_string(&path).unwrap();
+        let structure = conditionals(&text, &path).unwrap();
+        assert_eq!(structure.len(), 1);
+        assert_eq!(structure[0].macros(), vec!["A", "B", "C"]);
+        let branches = &structure[0].branches;
+        assert_eq!(branches.len(), 2);
+        assert_eq!(branches[0].condition, "A");
+        assert_eq!(branches[0].children[0].branches[0].macros, vec!["B"]);
+        assert_eq!(branches[1].directive, "else");
+        assert_eq!(branches[1].children[0].branches[0].directive, "ifndef");
+
+        // `B` isn't tested without `A`, and `C` isn't tested with `A`
+        let b = |x: &[u8]| x.iter().map(|x| *x == 1).collect::<Vec<_>>();
+        assert_eq!(
+            combinations(&text, &path, &HashMap::new(), &["A", "B", "C"]).unwrap(),
+            vec![b(&[0, 0, 0]), b(&[1, 0, 0]), b(&[1, 1, 0]), b(&[0, 0, 1])]
+        );
+        // `D` may be tested in included files
+        assert_eq!(
+            combinations(&text, &path, &HashMap::new(), &["A", "D"])
+                .unwrap()
+                .len(),
+            4
+        );
+
+        let include_paths: [PathBuf; 0] = [];
+        let variants: Vec<_> = parse_sv_variants(
+            &path,
+            &HashMap::new(),
+            &include_paths,
+            false,
+            &["A", "B", "C"],
+            &FileSystem,
+            &DefaultIncludeResolver,
+        )
+        .unwrap()
+        .map(|x| (x.defined, x.error.is_some()))
+        .collect();
+        let s = |x: &[&str]| x.iter().map(|x| String::from(*x)).collect::<Vec<_>>();
+        assert_eq!(
+            variants,
+            vec![
+                (s(&[]), false),
+                (s(&["A"]), false),
+                (s(&["A", "B"]), true),
+                (s(&["C"]), false),
+            ]
+        );
+
+        // the file is read by the provider
+        let mut files = MemoryFileProvider::new();
+        files.insert("memory.sv", text);
+        let variants: Vec<_> = parse_sv_variants(
+            "memory.sv",
+            &HashMap::new(),
+            &include_paths,
+            false,
+            &["A", "B"],
+            &files,
+            &DefaultIncludeResolver,
+        )
+        .unwrap()
+        .map(|x| (x.defined, x.error.is_some()))
+        .collect();
+        assert_eq!(
+            variants,
+            vec![(s(&[]), false), (s(&["A"]), false), (s(&["A", "B"]), true)]
+        );
+    }
+}
@@ -0,0 +1 @@
+-f testcases/filelist/loop.f
@@ -0,0 +1,2 @@
+sub.sv
++incdir+inc
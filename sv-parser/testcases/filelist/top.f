@@ -0,0 +1,3 @@
+top.sv
+-F testcases/filelist/sub/sub.f
+other.sv
@@ -0,0 +1 @@
+module cell; endmodule
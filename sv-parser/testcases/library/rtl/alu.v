@@ -0,0 +1 @@
+module alu; endmodule
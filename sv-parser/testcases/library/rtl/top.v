@@ -0,0 +1 @@
+module top; endmodule
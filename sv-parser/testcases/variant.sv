@@ -0,0 +1,12 @@
+module A;
+`ifdef A
+  `ifdef B
+  wire a = ;
+  `endif
+  wire b;
+`else
+  `ifndef C
+  wire c;
+  `endif
+`endif
+endmodule